@@ -0,0 +1,76 @@
+//! Compare `SlotMap::get` with `SlotMap::get_unchecked` over the same keys
+//!
+//! Run with `cargo bench --bench get_unchecked`. This has no harness, so it just
+//! times a few passes of each and prints the best, which is enough to see whether
+//! the unchecked path is worth keeping
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use school_binary_tree::{SlotKey, SlotMap};
+
+const ITEMS: usize = 1 << 16;
+const ROUNDS: usize = 200;
+const PASSES: usize = 5;
+
+/// Time `f` a few times and keep the fastest, to cut out noise from the rest of the system
+fn best_of<F>(mut f: F) -> Duration
+where
+    F: FnMut() -> u64,
+{
+    (0..PASSES)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .expect("There is at least one pass")
+}
+
+fn main() {
+    let mut slots = SlotMap::with_capacity(ITEMS);
+    let mut keys: Vec<SlotKey> = (0..ITEMS as u64).map(|item| slots.insert(item)).collect();
+
+    // Visit the slots out of order, like following links around a tree does
+    let mut state = 1u64;
+    for index in (1..keys.len()).rev() {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        keys.swap(index, (state >> 33) as usize % (index + 1));
+    }
+
+    let checked = best_of(|| {
+        let mut total = 0u64;
+        for _ in 0..ROUNDS {
+            for &key in &keys {
+                total = total.wrapping_add(*slots.get(black_box(key)));
+            }
+        }
+        total
+    });
+
+    let unchecked = best_of(|| {
+        let mut total = 0u64;
+        for _ in 0..ROUNDS {
+            for &key in &keys {
+                // SAFETY: Every key came from an insert and nothing has been removed
+                total = total.wrapping_add(unsafe { *slots.get_unchecked(black_box(key)) });
+            }
+        }
+        total
+    });
+
+    let lookups = (ITEMS * ROUNDS) as f64;
+    println!(
+        "get:           {:>10.2?} ({:.2} ns per lookup)",
+        checked,
+        checked.as_nanos() as f64 / lookups
+    );
+    println!(
+        "get_unchecked: {:>10.2?} ({:.2} ns per lookup)",
+        unchecked,
+        unchecked.as_nanos() as f64 / lookups
+    );
+}
@@ -0,0 +1,85 @@
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use crate::{slotmap::SlotKey, tree::Tree};
+
+/// Extra data attached to the nodes of a tree by key, kept outside the tree itself
+///
+/// Values are stored in a `Vec` indexed the same way as the tree's SlotMap, so a
+/// lookup is as cheap as getting the node. The caller keeps this alongside the tree
+///
+/// # WARNING
+///
+/// A freed slot can be reused by a later insert, so after removing nodes call
+/// `prune_freed` (or `remove` for the keys you know were freed) before inserting
+/// again, or a new node may pick up a stale attribute
+pub struct Attributes<A> {
+    values: Vec<Option<A>>,
+}
+
+impl<A> Debug for Attributes<A>
+where
+    A: Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Attributes")
+            .field("values", &self.values)
+            .finish()
+    }
+}
+
+impl<A> Default for Attributes<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A> Attributes<A> {
+    /// Create an empty attribute map
+    pub fn new() -> Self {
+        Self { values: Vec::new() }
+    }
+
+    /// Attach a value to a key, returning the one it had before
+    pub fn set(&mut self, key: SlotKey, value: A) -> Option<A> {
+        if self.values.len() <= key.index {
+            self.values.resize_with(key.index + 1, || None);
+        }
+
+        self.values[key.index].replace(value)
+    }
+
+    /// Get the value attached to a key
+    pub fn get(&self, key: SlotKey) -> Option<&A> {
+        self.values.get(key.index)?.as_ref()
+    }
+
+    /// Get mutable access to the value attached to a key
+    pub fn get_mut(&mut self, key: SlotKey) -> Option<&mut A> {
+        self.values.get_mut(key.index)?.as_mut()
+    }
+
+    /// Detach the value from a key, returning it
+    ///
+    /// This is the hook to call whenever the tree frees the node at `key`
+    pub fn remove(&mut self, key: SlotKey) -> Option<A> {
+        self.values.get_mut(key.index)?.take()
+    }
+
+    /// Detach every value
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    /// Detach the value from every key whose slot the tree has freed
+    ///
+    /// This catches up with any removals at once, rather than calling `remove`
+    /// for each freed key
+    pub fn prune_freed<T>(&mut self, tree: &Tree<T>) {
+        for (index, value) in self.values.iter_mut().enumerate() {
+            if value.is_some() && tree.node(SlotKey::new(index)).is_none() {
+                *value = None;
+            }
+        }
+    }
+}
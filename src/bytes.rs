@@ -0,0 +1,99 @@
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+
+/// A value that can be written to and read back from a compact byte buffer
+///
+/// Integers are stored little endian at their full width, with `usize` and `isize`
+/// widened to 64 bits so buffers are portable between targets
+pub trait ByteCodec: Sized {
+    /// Append the encoded value to `out`
+    fn encode(&self, out: &mut Vec<u8>);
+
+    /// Decode a value from the start of `bytes`, returning it and how many bytes it used
+    ///
+    /// Gives `None` if the bytes are too short or do not hold a valid value
+    fn decode(bytes: &[u8]) -> Option<(Self, usize)>;
+}
+
+macro_rules! impl_byte_codec_for_int {
+    ($($int:ty),*) => {
+        $(
+            impl ByteCodec for $int {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+                    const WIDTH: usize = size_of::<$int>();
+
+                    let value = bytes.get(..WIDTH)?.try_into().ok()?;
+                    Some((<$int>::from_le_bytes(value), WIDTH))
+                }
+            }
+        )*
+    };
+}
+
+impl_byte_codec_for_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl ByteCodec for usize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u64).encode(out);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let (value, used) = u64::decode(bytes)?;
+        Some((value.try_into().ok()?, used))
+    }
+}
+
+impl ByteCodec for isize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as i64).encode(out);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let (value, used) = i64::decode(bytes)?;
+        Some((value.try_into().ok()?, used))
+    }
+}
+
+impl ByteCodec for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        match bytes.first()? {
+            0 => Some((false, 1)),
+            1 => Some((true, 1)),
+            _ => None,
+        }
+    }
+}
+
+impl ByteCodec for char {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u32).encode(out);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let (value, used) = u32::decode(bytes)?;
+        Some((char::from_u32(value)?, used))
+    }
+}
+
+/// Stored as a 64 bit length followed by the UTF-8 bytes
+impl ByteCodec for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let (length, used) = usize::decode(bytes)?;
+        let end = used.checked_add(length)?;
+        let text = core::str::from_utf8(bytes.get(used..end)?).ok()?;
+
+        Some((text.to_owned(), end))
+    }
+}
@@ -0,0 +1,34 @@
+use core::fmt::Debug;
+
+use crate::{slotmap::SlotKey, tree::Tree};
+
+/// Refers to a value in a tree by the value itself rather than by its node
+///
+/// A `SlotKey` stops being valid once its node is removed, and `rebuild`,
+/// `defragment` and auto compaction move every value into fresh storage under new
+/// keys. A handle instead looks its value up again with `resolve`, so it keeps working
+/// across those at the cost of an O(height) search
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handle<T>(pub T);
+
+impl<T> Handle<T>
+where
+    T: Eq + Ord + Debug,
+{
+    /// Find the key of the node currently holding this value, or `None` if it is gone
+    pub fn resolve(&self, tree: &Tree<T>) -> Option<SlotKey> {
+        tree.find_with_depth(&self.0).map(|(node_key, _)| node_key)
+    }
+}
+
+impl<T> Tree<T>
+where
+    T: Eq + Ord + Debug + Clone,
+{
+    /// Make a handle to the value held by the node a key points at
+    ///
+    /// Gives `None` if that slot is empty
+    pub fn handle(&self, key: SlotKey) -> Option<Handle<T>> {
+        self.get_by_key(key).cloned().map(Handle)
+    }
+}
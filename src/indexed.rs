@@ -0,0 +1,183 @@
+use core::{fmt::Debug, hash::Hash};
+use std::collections::HashMap;
+
+use crate::{
+    slotmap::SlotKey,
+    tree::{InsertError, Tree, TreeOrdering},
+};
+
+/// A tree that also keeps a map from each value to the key of its node
+///
+/// Made with `Tree::with_index`. Finding a value is then a hash lookup instead of a
+/// descent from the root, at the cost of a clone of every value
+///
+/// # NOTE
+///
+/// Restructuring the tree, such as removing a node with two children, relinks
+/// nodes rather than moving values between them, so the key of every other value
+/// stays valid and the index only changes for the value inserted or removed
+pub struct IndexedTree<T> {
+    tree: Tree<T>,
+    index: HashMap<T, SlotKey>,
+}
+
+impl<T> Debug for IndexedTree<T>
+where
+    T: Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("IndexedTree")
+            .field("tree", &self.tree)
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+impl<T> Tree<T>
+where
+    T: Eq + Ord + Hash + Clone + Debug,
+{
+    /// Start keeping an index from each value to the key of its node
+    pub fn with_index(self) -> IndexedTree<T> {
+        let index = self
+            .iter_keys(TreeOrdering::In)
+            .map(|node_key| {
+                let data = self
+                    .get_by_key(node_key)
+                    .expect("The key was just taken from the tree");
+
+                (data.clone(), node_key)
+            })
+            .collect();
+
+        IndexedTree { tree: self, index }
+    }
+}
+
+impl<T> IndexedTree<T>
+where
+    T: Eq + Ord + Hash + Clone + Debug,
+{
+    /// Insert data into the tree, returning the key of the node holding it
+    ///
+    /// # NOTE
+    ///
+    /// This follows the tree's duplicate policy, just like `Tree::insert_ordered_key`
+    pub fn insert(&mut self, data: T) -> Result<SlotKey, InsertError<T>> {
+        let node_key = self.tree.insert_ordered_key(data)?;
+
+        if let Some(data) = self.tree.get_by_key(node_key)
+            && !self.index.contains_key(data)
+        {
+            self.index.insert(data.clone(), node_key);
+        }
+
+        Ok(node_key)
+    }
+
+    /// Remove data from the tree in place, returning it if it existed
+    pub fn remove(&mut self, data: &T) -> Option<T> {
+        let node_key = self.index.remove(data)?;
+
+        Some(self.tree.remove_key(node_key))
+    }
+
+    /// Find the key of the node holding data, without searching the tree
+    pub fn find(&self, data: &T) -> Option<SlotKey> {
+        self.index.get(data).copied()
+    }
+
+    /// Check if tree contains, without searching the tree
+    pub fn contains(&self, data: &T) -> bool {
+        self.index.contains_key(data)
+    }
+
+    /// Count the values in the tree
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Check if the tree has no values
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Get read only access to the tree, for everything that does not change it
+    pub fn tree(&self) -> &Tree<T> {
+        &self.tree
+    }
+
+    /// Stop keeping the index, giving back the tree
+    pub fn into_tree(self) -> Tree<T> {
+        self.tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::DuplicatePolicy;
+
+    /// Check every value left in the tree is indexed at the key a search finds it at
+    fn assert_index_agrees(indexed: &IndexedTree<i32>, values: &[i32]) {
+        assert_eq!(indexed.len(), values.len());
+
+        for value in values {
+            let searched = indexed
+                .tree()
+                .find_with_depth(value)
+                .map(|(node_key, _)| node_key);
+
+            assert!(searched.is_some(), "{} should still be in the tree", value);
+            assert_eq!(
+                indexed.find(value),
+                searched,
+                "Index for {} is stale",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn index_survives_rebalancing_and_removal() {
+        // Sorted inserts leave a chain unless the rebalance relinks it
+        let mut indexed = Tree::empty().with_rebalance_factor(1.5).with_index();
+        let mut values: Vec<i32> = (0..64).collect();
+        for &value in &values {
+            indexed.insert(value).unwrap();
+        }
+        assert!(indexed.tree().stats().height < 16);
+        assert_index_agrees(&indexed, &values);
+
+        // The root of a balanced tree, and most of the values near it, have two children
+        while values.len() > 8 {
+            let root_key = indexed.tree().root_key().unwrap();
+            let root = *indexed.tree().get_by_key(root_key).unwrap();
+            assert_eq!(indexed.remove(&root), Some(root));
+
+            values.retain(|&value| value != root);
+            assert!(!indexed.contains(&root));
+            assert_index_agrees(&indexed, &values);
+        }
+
+        assert_eq!(indexed.remove(&100), None);
+        assert_index_agrees(&indexed, &values);
+    }
+
+    #[test]
+    fn index_keeps_the_key_when_replacing() {
+        let mut indexed = Tree::empty()
+            .with_duplicate_policy(DuplicatePolicy::Replace)
+            .with_index();
+        for value in [4, 2, 6, 1, 3, 5, 7] {
+            indexed.insert(value).unwrap();
+        }
+
+        // The new value overwrites the old one in its node, handing the old one back
+        let key = indexed.find(&3).unwrap();
+        assert!(matches!(indexed.insert(3), Err(InsertError::Duplicate(3))));
+        assert_eq!(indexed.find(&3), Some(key));
+        assert_eq!(indexed.tree().get_by_key(key), Some(&3));
+        assert_index_agrees(&indexed, &[1, 2, 3, 4, 5, 6, 7]);
+    }
+}
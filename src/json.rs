@@ -0,0 +1,132 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+
+/// Append text to `out` as a quoted JSON string, escaping anything that needs it
+pub(crate) fn write_string(out: &mut String, text: &str) {
+    out.push('"');
+
+    for character in text.chars() {
+        match character {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            // Every other control character must be written as a code point
+            '\u{0}'..='\u{1f}' => {
+                write!(out, "\\u{:04x}", character as u32).expect("Writing to a String cannot fail")
+            }
+            _ => out.push(character),
+        }
+    }
+
+    out.push('"');
+}
+
+/// Parse a JSON array whose elements are strings or bare scalars such as numbers
+///
+/// Strings are unescaped and bare scalars are given back as written, leaving the
+/// caller to parse them. Nested arrays and objects are not supported and give `None`
+pub(crate) fn parse_array(text: &str) -> Option<Vec<String>> {
+    let mut chars = text.trim().chars().peekable();
+    let mut out = Vec::new();
+
+    if chars.next()? != '[' {
+        return None;
+    }
+    skip_whitespace(&mut chars);
+
+    if chars.peek() == Some(&']') {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace(&mut chars);
+            out.push(parse_element(&mut chars)?);
+            skip_whitespace(&mut chars);
+
+            match chars.next()? {
+                ',' => continue,
+                ']' => break,
+                _ => return None,
+            }
+        }
+    }
+
+    // Nothing may follow the closing bracket
+    chars.next().is_none().then_some(out)
+}
+
+type Chars<'a> = core::iter::Peekable<core::str::Chars<'a>>;
+
+fn skip_whitespace(chars: &mut Chars) {
+    while chars
+        .next_if(|character| character.is_whitespace())
+        .is_some()
+    {}
+}
+
+/// Parse a single array element, stopping before the comma or bracket after it
+fn parse_element(chars: &mut Chars) -> Option<String> {
+    if chars.next_if_eq(&'"').is_none() {
+        // A bare scalar runs until the next separator
+        let mut scalar = String::new();
+        while let Some(character) =
+            chars.next_if(|character| !matches!(character, ',' | ']' | '[' | '{' | '"' | '}'))
+        {
+            scalar.push(character);
+        }
+
+        let scalar = scalar.trim_end();
+        return (!scalar.is_empty()).then(|| scalar.into());
+    }
+
+    let mut string = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(string),
+            '\\' => string.push(match chars.next()? {
+                '"' => '"',
+                '\\' => '\\',
+                '/' => '/',
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'u' => parse_unicode_escape(chars)?,
+                _ => return None,
+            }),
+            character => string.push(character),
+        }
+    }
+}
+
+/// Parse the code point after a `\u`, including the second half of a surrogate pair
+fn parse_unicode_escape(chars: &mut Chars) -> Option<char> {
+    let high = parse_hex4(chars)?;
+
+    if !(0xD800..0xDC00).contains(&high) {
+        return char::from_u32(high);
+    }
+
+    // A high surrogate must be followed by an escaped low surrogate
+    if chars.next()? != '\\' || chars.next()? != 'u' {
+        return None;
+    }
+    let low = parse_hex4(chars)?;
+    if !(0xDC00..0xE000).contains(&low) {
+        return None;
+    }
+
+    char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+}
+
+fn parse_hex4(chars: &mut Chars) -> Option<u32> {
+    let mut value = 0;
+
+    for _ in 0..4 {
+        value = value * 16 + chars.next()?.to_digit(16)?;
+    }
+
+    Some(value)
+}
@@ -0,0 +1,31 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod attributes;
+pub mod bytes;
+pub mod handle;
+#[cfg(feature = "std")]
+pub mod indexed;
+mod json;
+pub mod rbtree;
+mod rotation;
+pub mod slotmap;
+pub mod treap;
+pub mod tree;
+pub mod wbtree;
+
+pub use attributes::Attributes;
+pub use bytes::ByteCodec;
+pub use handle::Handle;
+#[cfg(feature = "std")]
+pub use indexed::IndexedTree;
+pub use rbtree::RbTree;
+pub use slotmap::{BitChunk, SlotKey, SlotMap, SlotMapStats};
+pub use treap::Treap;
+pub use tree::{
+    Cursor, CursorMut, DuplicatePolicy, InOrderIter, InsertError, InsertOutcome, MergeSide,
+    ParseError, ParseTreeOrderingError, PostOrderIter, PreOrderIter, RotateError, SwapError, Tree,
+    TreeDirection, TreeError, TreeNode, TreeOrdering, TreeStats, WalkControl,
+};
+pub use wbtree::WbTree;
@@ -0,0 +1,450 @@
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::Debug;
+
+use crate::{
+    rotation::{self, BinaryNode},
+    slotmap::{SlotKey, SlotMap},
+    tree::TreeDirection,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Color {
+    Red,
+    Black,
+}
+
+struct RbNode<T> {
+    data: T,
+    color: Color,
+    left: Option<SlotKey>,
+    right: Option<SlotKey>,
+}
+
+impl<T> Debug for RbNode<T>
+where
+    T: Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RbNode")
+            .field("data", &self.data)
+            .field("color", &self.color)
+            .field("left", &self.left)
+            .field("right", &self.right)
+            .finish()
+    }
+}
+
+impl<T> RbNode<T> {
+    /// New nodes start red, so adding one never changes a black height
+    fn new(data: T) -> Self {
+        Self {
+            data,
+            color: Color::Red,
+            left: None,
+            right: None,
+        }
+    }
+}
+
+impl<T> BinaryNode for RbNode<T> {
+    type Data = T;
+
+    fn data(&self) -> &T {
+        &self.data
+    }
+
+    fn child(&self, direction: TreeDirection) -> Option<SlotKey> {
+        match direction {
+            TreeDirection::Left => self.left,
+            TreeDirection::Right => self.right,
+        }
+    }
+
+    fn set_child(&mut self, direction: TreeDirection, child: Option<SlotKey>) {
+        match direction {
+            TreeDirection::Left => self.left = child,
+            TreeDirection::Right => self.right = child,
+        }
+    }
+}
+
+/// A BST that balances itself by colouring every node red or black
+///
+/// No red node has a red child, and every path from a node down to a missing child
+/// passes the same number of black nodes. Together these keep the longest path at
+/// most twice the shortest, so the height is at most 2 * log2(n + 1). Fixing the
+/// colours after a change is mostly recolouring, with at most two rotations per
+/// insert and three per remove
+pub struct RbTree<T> {
+    storage: SlotMap<RbNode<T>>,
+    /// `None` when the tree is empty
+    root: Option<SlotKey>,
+}
+
+impl<T> Debug for RbTree<T>
+where
+    T: Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RbTree")
+            .field("storage", &self.storage)
+            .field("root", &self.root)
+            .finish()
+    }
+}
+
+impl<T> Default for RbTree<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> RbTree<T> {
+    /// Create an empty red-black tree
+    pub fn new() -> Self {
+        Self {
+            storage: SlotMap::new(),
+            root: None,
+        }
+    }
+
+    /// Count the values in the tree
+    pub fn len(&self) -> usize {
+        self.storage.item_count
+    }
+
+    /// Check if the tree has no values
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Check if a node is red, a missing child counts as black
+    fn is_red(&self, node: Option<SlotKey>) -> bool {
+        node.is_some_and(|key| self.storage.get(key).color == Color::Red)
+    }
+
+    fn set_color(&mut self, node_key: SlotKey, color: Color) {
+        self.storage.get_mut(node_key).color = color;
+    }
+
+    /// Point a parent's child, or the root if there is no parent, at a node
+    fn link(&mut self, parent: Option<(SlotKey, TreeDirection)>, child: Option<SlotKey>) {
+        rotation::link(&mut self.storage, &mut self.root, parent, child);
+    }
+
+    /// Restore the colour rules after a red node was hung from the end of `path`
+    fn fix_after_insert(&mut self, mut node_key: SlotKey, mut path: Vec<(SlotKey, TreeDirection)>) {
+        loop {
+            let Some(&(parent_key, parent_side)) = path.last() else {
+                // The node is the root, which is always black
+                self.set_color(node_key, Color::Black);
+                return;
+            };
+            if !self.is_red(Some(parent_key)) {
+                return;
+            }
+
+            // A red parent is never the root, so there is a grandparent
+            let (grand_key, grand_side) = path[path.len() - 2];
+            let uncle = self.storage.get(grand_key).child(grand_side.opposite());
+
+            if let Some(uncle_key) = uncle
+                && self.is_red(uncle)
+            {
+                // Push the red up to the grandparent, which may clash with its own parent
+                self.set_color(parent_key, Color::Black);
+                self.set_color(uncle_key, Color::Black);
+                self.set_color(grand_key, Color::Red);
+
+                node_key = grand_key;
+                path.truncate(path.len() - 2);
+                continue;
+            }
+
+            let mut parent_key = parent_key;
+            if parent_side != grand_side {
+                // The node is on the inside, so lift it above its parent first
+                parent_key = rotation::rotate(&mut self.storage, parent_key, grand_side);
+                self.storage
+                    .get_mut(grand_key)
+                    .set_child(grand_side, Some(parent_key));
+            }
+
+            let lifted = rotation::rotate(&mut self.storage, grand_key, grand_side.opposite());
+            self.set_color(lifted, Color::Black);
+            self.set_color(grand_key, Color::Red);
+            // The lifted node takes the grandparent's place, under its parent if it has one
+            self.link(path.iter().rev().nth(2).copied(), Some(lifted));
+
+            return;
+        }
+    }
+
+    /// Restore the colour rules after a black node was taken from the end of `path`,
+    /// leaving its side one black short
+    fn fix_after_remove(&mut self, mut path: Vec<(SlotKey, TreeDirection)>) {
+        while let Some(&(parent_key, side)) = path.last() {
+            let sibling_key = self
+                .storage
+                .get(parent_key)
+                .child(side.opposite())
+                .expect("The other side has a black more, so it has a node");
+
+            if self.is_red(Some(sibling_key)) {
+                // Lift the red sibling so the short side gets a black sibling instead
+                self.set_color(sibling_key, Color::Black);
+                self.set_color(parent_key, Color::Red);
+
+                let lifted = rotation::rotate(&mut self.storage, parent_key, side);
+                path.pop();
+                self.link(path.last().copied(), Some(lifted));
+                path.push((lifted, side));
+                path.push((parent_key, side));
+                continue;
+            }
+
+            let sibling = self.storage.get(sibling_key);
+            let (near, far) = (sibling.child(side), sibling.child(side.opposite()));
+
+            if !self.is_red(near) && !self.is_red(far) {
+                // Take a black from the sibling's side too, moving the shortfall up
+                self.set_color(sibling_key, Color::Red);
+
+                if self.is_red(Some(parent_key)) {
+                    self.set_color(parent_key, Color::Black);
+                    return;
+                }
+
+                path.pop();
+                continue;
+            }
+
+            let mut sibling_key = sibling_key;
+            if !self.is_red(far) {
+                // Move the red nephew to the outside, where the last rotation needs it
+                let near_key = near.expect("One of the nephews is red");
+                self.set_color(near_key, Color::Black);
+                self.set_color(sibling_key, Color::Red);
+
+                sibling_key = rotation::rotate(&mut self.storage, sibling_key, side.opposite());
+                self.storage
+                    .get_mut(parent_key)
+                    .set_child(side.opposite(), Some(sibling_key));
+            }
+
+            let far_key = self
+                .storage
+                .get(sibling_key)
+                .child(side.opposite())
+                .expect("The far nephew is red");
+            let parent_color = self.storage.get(parent_key).color;
+            self.set_color(sibling_key, parent_color);
+            self.set_color(parent_key, Color::Black);
+            self.set_color(far_key, Color::Black);
+
+            let lifted = rotation::rotate(&mut self.storage, parent_key, side);
+            path.pop();
+            self.link(path.last().copied(), Some(lifted));
+
+            return;
+        }
+    }
+
+    /// Get the number of nodes on the longest path from the root to a leaf
+    pub fn height(&self) -> usize {
+        rotation::height(&self.storage, self.root)
+    }
+
+    /// Collect references to every value in ascending order
+    pub fn to_vec(&self) -> Vec<&T> {
+        rotation::to_vec(&self.storage, self.root)
+    }
+
+    /// Check the colour rules hold, giving the black height if they do
+    ///
+    /// The root must be black, no red node may have a red child and every path down
+    /// to a missing child must pass the same number of black nodes. The black height
+    /// counts the black nodes on each such path, so an empty tree has zero
+    pub fn check_invariants(&self) -> Result<usize, String> {
+        if self.is_red(self.root) {
+            return Err(format!("The root {:?} is red", self.root));
+        }
+
+        let mut black_height = None;
+
+        // Each entry is a node still to check and the black nodes above it
+        let mut stack = Vec::new();
+        stack.push((self.root, 0));
+
+        while let Some((current, blacks_above)) = stack.pop() {
+            let Some(current_key) = current else {
+                // Every path ends here, so they must all agree
+                match black_height {
+                    None => black_height = Some(blacks_above),
+                    Some(expected) if expected != blacks_above => {
+                        return Err(format!(
+                            "A path passes {} black nodes where another passes {}",
+                            blacks_above, expected
+                        ));
+                    }
+                    Some(_) => {}
+                }
+                continue;
+            };
+            let current_node = self.storage.get(current_key);
+
+            let blacks = match current_node.color {
+                Color::Black => blacks_above + 1,
+                Color::Red => {
+                    if self.is_red(current_node.left) || self.is_red(current_node.right) {
+                        return Err(format!("Red node {:?} has a red child", current_key));
+                    }
+                    blacks_above
+                }
+            };
+
+            stack.push((current_node.left, blacks));
+            stack.push((current_node.right, blacks));
+        }
+
+        Ok(black_height.unwrap_or(0))
+    }
+}
+
+impl<T> RbTree<T>
+where
+    T: Eq + Ord + Debug,
+{
+    /// Insert data into the tree
+    ///
+    /// # NOTE
+    ///
+    /// If the data already exists in the tree, then it just returns the data
+    pub fn insert(&mut self, data: T) -> Result<(), T> {
+        // The nodes passed on the way down, and which way we went at each
+        let (path, found) = rotation::descend(&self.storage, self.root, &data);
+        if found.is_some() {
+            // We cannot accept duplicates
+            return Err(data);
+        }
+
+        let new_key = self.storage.insert(RbNode::new(data));
+        self.link(path.last().copied(), Some(new_key));
+
+        self.fix_after_insert(new_key, path);
+
+        Ok(())
+    }
+
+    /// Check if tree contains
+    pub fn contains(&self, data: &T) -> bool {
+        rotation::contains(&self.storage, self.root, data)
+    }
+
+    /// Remove data from the tree, returning it if it existed
+    pub fn remove(&mut self, data: &T) -> Option<T> {
+        // Locate the node holding the data
+        let (mut path, found) = rotation::descend(&self.storage, self.root, data);
+        let node_key = found?;
+
+        let node = self.storage.get(node_key);
+        let (removed, removed_color, child) = match (node.left, node.right) {
+            (Some(_), Some(right)) => {
+                // Swap in the smallest value on the right, and remove its node instead
+                path.push((node_key, TreeDirection::Right));
+
+                let mut successor = right;
+                while let Some(left) = self.storage.get(successor).left {
+                    path.push((successor, TreeDirection::Left));
+                    successor = left;
+                }
+
+                let successor_node = self.storage.remove(successor);
+                self.link(path.last().copied(), successor_node.right);
+
+                let removed = core::mem::replace(
+                    &mut self.storage.get_mut(node_key).data,
+                    successor_node.data,
+                );
+                (removed, successor_node.color, successor_node.right)
+            }
+            (child, None) | (None, child) => {
+                self.link(path.last().copied(), child);
+
+                let removed_node = self.storage.remove(node_key);
+                (removed_node.data, removed_node.color, child)
+            }
+        };
+
+        if removed_color == Color::Black {
+            match child {
+                // A red child can simply take the black that was lost
+                Some(child_key) if self.is_red(child) => self.set_color(child_key, Color::Black),
+                _ => self.fix_after_remove(path),
+            }
+        }
+
+        Some(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::BTreeSet;
+
+    use super::*;
+
+    /// A small seeded generator, so failures can be replayed
+    fn next(state: &mut u64) -> u64 {
+        *state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        *state >> 33
+    }
+
+    fn assert_matches(tree: &RbTree<u32>, reference: &BTreeSet<u32>) {
+        tree.check_invariants().unwrap();
+        assert_eq!(tree.len(), reference.len());
+        assert!(tree.to_vec().into_iter().eq(reference.iter()));
+    }
+
+    #[test]
+    fn random_inserts_and_removes_keep_the_rules() {
+        for seed in 0..20 {
+            let mut state = seed;
+            let mut tree = RbTree::new();
+            let mut reference = BTreeSet::new();
+
+            for _ in 0..500 {
+                let value = (next(&mut state) % 200) as u32;
+
+                if next(&mut state).is_multiple_of(3) {
+                    assert_eq!(tree.remove(&value), reference.take(&value));
+                } else {
+                    assert_eq!(tree.insert(value).is_ok(), reference.insert(value));
+                }
+
+                assert_matches(&tree, &reference);
+            }
+
+            for value in reference.clone() {
+                assert_eq!(tree.remove(&value), Some(value));
+                reference.remove(&value);
+                assert_matches(&tree, &reference);
+            }
+            assert!(tree.is_empty());
+        }
+    }
+
+    #[test]
+    fn black_height_stays_logarithmic_for_sorted_input() {
+        let mut tree = RbTree::new();
+        for value in 0..1024 {
+            tree.insert(value).unwrap();
+        }
+
+        let black_height = tree.check_invariants().unwrap();
+        assert!(black_height <= 11, "black height {}", black_height);
+        assert!(tree.height() <= 2 * 11);
+    }
+}
@@ -0,0 +1,180 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use core::cmp::Ordering;
+
+use crate::{
+    slotmap::{SlotKey, SlotMap},
+    tree::TreeDirection,
+};
+
+/// A node linking to its children by key, which is all a rotation needs to know about it
+///
+/// The value it holds is only needed by the shared searches and walks below
+pub(crate) trait BinaryNode {
+    type Data;
+
+    /// Get the value held by the node
+    fn data(&self) -> &Self::Data;
+
+    /// Get the child on one side
+    fn child(&self, direction: TreeDirection) -> Option<SlotKey>;
+
+    /// Point the child on one side at a different node
+    fn set_child(&mut self, direction: TreeDirection, child: Option<SlotKey>);
+
+    /// Point the node back at its parent, nodes without parent links can ignore this
+    fn set_parent(&mut self, _parent: Option<SlotKey>) {}
+}
+
+/// Rotate the subtree rooted at `key`, returning the key of its new root
+///
+/// A left rotation lifts the right child and a right rotation lifts the left child,
+/// either way the in-order sequence is unchanged. The caller must point whatever
+/// referred to `key` (a parent or the tree root) at the returned key, and set the
+/// parent of the returned node to match
+///
+/// # Panics
+///
+/// If there is no child on the side being lifted
+pub(crate) fn rotate<N>(storage: &mut SlotMap<N>, key: SlotKey, direction: TreeDirection) -> SlotKey
+where
+    N: BinaryNode,
+{
+    let lifted_side = direction.opposite();
+    let lifted = storage
+        .get(key)
+        .child(lifted_side)
+        .expect("Rotation needs a child to lift");
+
+    // The subtree between the two nodes swaps from the lifted node over to the old root
+    let inner = storage.get(lifted).child(direction);
+
+    storage.get_mut(key).set_child(lifted_side, inner);
+    storage.get_mut(lifted).set_child(direction, Some(key));
+
+    if let Some(inner) = inner {
+        storage.get_mut(inner).set_parent(Some(key));
+    }
+    storage.get_mut(key).set_parent(Some(lifted));
+
+    lifted
+}
+
+/// Point a parent's child, or the root if there is no parent, at a node
+pub(crate) fn link<N>(
+    storage: &mut SlotMap<N>,
+    root: &mut Option<SlotKey>,
+    parent: Option<(SlotKey, TreeDirection)>,
+    child: Option<SlotKey>,
+) where
+    N: BinaryNode,
+{
+    match parent {
+        Some((parent_key, direction)) => storage.get_mut(parent_key).set_child(direction, child),
+        None => *root = child,
+    }
+}
+
+/// Get the number of nodes on the longest path from the root to a leaf
+pub(crate) fn height<N>(storage: &SlotMap<N>, root: Option<SlotKey>) -> usize
+where
+    N: BinaryNode,
+{
+    let mut height = 0;
+
+    let mut queue = VecDeque::<(SlotKey, usize)>::new();
+    queue.extend(root.map(|root| (root, 1)));
+
+    while let Some((current_key, depth)) = queue.pop_front() {
+        let current_node = storage.get(current_key);
+        height = depth;
+
+        for direction in [TreeDirection::Left, TreeDirection::Right] {
+            if let Some(child) = current_node.child(direction) {
+                queue.push_back((child, depth + 1));
+            }
+        }
+    }
+
+    height
+}
+
+/// Collect references to every value below the root in ascending order
+pub(crate) fn to_vec<N>(storage: &SlotMap<N>, root: Option<SlotKey>) -> Vec<&N::Data>
+where
+    N: BinaryNode,
+{
+    let mut out = Vec::with_capacity(storage.item_count);
+
+    let mut stack = Vec::new();
+    let mut current = root;
+
+    loop {
+        while let Some(current_key) = current {
+            stack.push(current_key);
+            current = storage.get(current_key).child(TreeDirection::Left);
+        }
+
+        let Some(node_key) = stack.pop() else {
+            break;
+        };
+
+        let node = storage.get(node_key);
+        out.push(node.data());
+        current = node.child(TreeDirection::Right);
+    }
+
+    out
+}
+
+/// Search down from the root for data, giving the nodes passed and which way the
+/// search went at each, along with the node holding data if there is one
+///
+/// When data is missing, the last step of the path is where it would be hung as a leaf
+pub(crate) fn descend<N>(
+    storage: &SlotMap<N>,
+    root: Option<SlotKey>,
+    data: &N::Data,
+) -> (Vec<(SlotKey, TreeDirection)>, Option<SlotKey>)
+where
+    N: BinaryNode,
+    N::Data: Ord,
+{
+    let mut path = Vec::new();
+    let mut current = root;
+
+    while let Some(current_key) = current {
+        let current_node = storage.get(current_key);
+
+        let direction = match data.cmp(current_node.data()) {
+            Ordering::Equal => return (path, Some(current_key)),
+            Ordering::Less => TreeDirection::Left,
+            Ordering::Greater => TreeDirection::Right,
+        };
+
+        path.push((current_key, direction));
+        current = current_node.child(direction);
+    }
+
+    (path, None)
+}
+
+/// Check if data is held by any node below the root
+pub(crate) fn contains<N>(storage: &SlotMap<N>, root: Option<SlotKey>, data: &N::Data) -> bool
+where
+    N: BinaryNode,
+    N::Data: Ord,
+{
+    let mut current = root;
+
+    while let Some(current_key) = current {
+        let current_node = storage.get(current_key);
+
+        current = match data.cmp(current_node.data()) {
+            Ordering::Equal => return true,
+            Ordering::Less => current_node.child(TreeDirection::Left),
+            Ordering::Greater => current_node.child(TreeDirection::Right),
+        };
+    }
+
+    false
+}
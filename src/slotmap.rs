@@ -0,0 +1,706 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use core::{
+    fmt::Debug,
+    ops::{BitAnd, BitAndAssign, BitOrAssign, Index, IndexMut, Not},
+};
+
+pub struct Slot<T> {
+    pub(crate) item: Option<T>,
+}
+
+impl<T> Slot<T> {
+    fn new(item: T) -> Self {
+        Self { item: Some(item) }
+    }
+
+    /// Clear this slot
+    fn clear(&mut self) -> Option<T> {
+        self.item.take()
+    }
+
+    /// Set the value of this slot
+    fn set(&mut self, item: T) {
+        self.item = Some(item);
+    }
+}
+
+impl<T> Debug for Slot<T>
+where
+    T: Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Slot").field("item", &self.item).finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SlotKey {
+    pub(crate) index: usize,
+}
+
+impl SlotKey {
+    pub(crate) fn new(index: usize) -> Self {
+        Self { index }
+    }
+}
+
+/// An unsigned integer used as one chunk of the free slot bitmap
+///
+/// Implemented for `u8` up to `u128`. Narrow chunks waste less space in small
+/// SlotMaps, while wide ones let `find_free_slot` skip more full slots at once
+pub trait BitChunk:
+    Copy + Eq + Debug + BitAnd<Output = Self> + BitAndAssign + BitOrAssign + Not<Output = Self>
+{
+    /// Number of slots one chunk covers
+    const BITS: u32;
+    /// A chunk with every bit set, marking all of its slots full
+    const FULL: Self;
+    /// A chunk with no bits set
+    const EMPTY: Self;
+
+    /// A chunk with only the bit at this offset from the least significant end set
+    fn bit(offset: u32) -> Self;
+
+    /// Count the set bits from the most significant end
+    fn leading_ones(self) -> u32;
+}
+
+macro_rules! impl_bit_chunk {
+    ($($int:ty),*) => {
+        $(
+            impl BitChunk for $int {
+                const BITS: u32 = <$int>::BITS;
+                const FULL: Self = <$int>::MAX;
+                const EMPTY: Self = 0;
+
+                fn bit(offset: u32) -> Self {
+                    1 << offset
+                }
+
+                fn leading_ones(self) -> u32 {
+                    <$int>::leading_ones(self)
+                }
+            }
+        )*
+    };
+}
+
+impl_bit_chunk!(u8, u16, u32, u64, u128);
+
+/// Find the bitmap chunk holding a slot's bit, and the mask selecting that bit
+///
+/// Bits are stored most significant first, so the first slot of a chunk is its top
+/// bit. The remainder is always below the chunk width, so the shift cannot overflow,
+/// and dividing cannot overflow, so this is correct for every index up to `usize::MAX`.
+/// The chunk index is at most `usize::MAX / C::BITS`, so one more than it also fits
+fn bit_position<C>(index: usize) -> (usize, C)
+where
+    C: BitChunk,
+{
+    let bit_length = C::BITS as usize;
+    let bit_offset = bit_length - 1 - (index % bit_length);
+
+    (index / bit_length, C::bit(bit_offset as u32))
+}
+
+/// Turn a bitmap chunk and a bit offset within it back into a slot index
+///
+/// The inverse of `bit_position`, returning `None` instead of wrapping when the
+/// index would not fit in a `usize`
+fn slot_index<C>(chunk_index: usize, bit_offset: u32) -> Option<usize>
+where
+    C: BitChunk,
+{
+    chunk_index
+        .checked_mul(C::BITS as usize)
+        .and_then(|chunk_start| chunk_start.checked_add(bit_offset as usize))
+}
+
+/// Stores items in reusable slots, handing out a `SlotKey` for each
+///
+/// Free slots are tracked in a bitmap made of `C` chunks, `u64` by default. Other
+/// widths are made with `Default`, e.g. `SlotMap::<T, u32>::default()`
+pub struct SlotMap<T, C = u64> {
+    pub(crate) slots: Vec<Slot<T>>,
+    pub(crate) item_count: usize,
+    /// 1 bit represents full slot and 0 bit represents empty slot
+    empty_indexes: Vec<C>,
+    /// Bumped by every call that could change an item or which slots are full, so a
+    /// cache built from the items can tell when it is stale
+    pub(crate) changes: u64,
+}
+
+impl<T, C> Debug for SlotMap<T, C>
+where
+    T: Debug,
+    C: BitChunk,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SlotMap")
+            .field("slots", &self.slots)
+            .field("item_count", &self.item_count)
+            .field("empty_indexes", &self.empty_indexes)
+            .finish()
+    }
+}
+
+/// A snapshot of how much of a SlotMap is in use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotMapStats {
+    /// Number of occupied slots
+    pub live: usize,
+    /// Number of slots, occupied or not
+    pub total_slots: usize,
+    /// Number of slots waiting to be reused
+    pub free_slots: usize,
+    /// Number of chunks in the free slot bitmap
+    pub bitmap_chunks: usize,
+}
+
+impl<T, C> Default for SlotMap<T, C>
+where
+    C: BitChunk,
+{
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            item_count: 0,
+            empty_indexes: Vec::new(),
+            changes: 0,
+        }
+    }
+}
+
+/// Same as `get`, so it panics if the key does not point at an item
+impl<T, C> Index<SlotKey> for SlotMap<T, C>
+where
+    C: BitChunk,
+{
+    type Output = T;
+
+    fn index(&self, slot_key: SlotKey) -> &T {
+        self.get(slot_key)
+    }
+}
+
+/// Same as `get_mut`, so it panics if the key does not point at an item
+impl<T, C> IndexMut<SlotKey> for SlotMap<T, C>
+where
+    C: BitChunk,
+{
+    fn index_mut(&mut self, slot_key: SlotKey) -> &mut T {
+        self.get_mut(slot_key)
+    }
+}
+
+impl<T> SlotMap<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty SlotMap with room for `capacity` items, and their bits in the
+    /// free slot bitmap, before it has to reallocate
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(capacity),
+            item_count: 0,
+            empty_indexes: Vec::with_capacity(capacity.div_ceil(u64::BITS as usize)),
+            changes: 0,
+        }
+    }
+}
+
+impl<T, C> SlotMap<T, C>
+where
+    C: BitChunk,
+{
+    /// Get the number of slots, which only grows as the SlotMap never shrinks
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Report how many slots are live, free and allocated in total
+    pub fn stats(&self) -> SlotMapStats {
+        SlotMapStats {
+            live: self.item_count,
+            total_slots: self.slots.len(),
+            free_slots: self.slots.len() - self.item_count,
+            bitmap_chunks: self.empty_indexes.len(),
+        }
+    }
+
+    /// Note that the items may be about to change
+    fn touch(&mut self) {
+        self.changes = self.changes.wrapping_add(1);
+    }
+
+    /// Find the next free slot in the SlotMap
+    pub fn find_free_slot(&self) -> Option<usize> {
+        for (chunk_index, &empty_chunk) in self.empty_indexes.iter().enumerate() {
+            let free_slot = empty_chunk.leading_ones();
+
+            if free_slot >= C::BITS {
+                // There are no free slots in this chunk
+                continue;
+            }
+
+            // There is a free slot at next_slot
+            // Free bits are only ever cleared for slots that exist, so this fits unless
+            // the bitmap is corrupt, in which case wrapping would hand out the wrong slot
+            let free_index = slot_index::<C>(chunk_index, free_slot)
+                .expect("A free bit must belong to a slot whose index fits in a usize");
+
+            return Some(free_index);
+        }
+
+        None
+    }
+
+    pub fn insert(&mut self, item: T) -> SlotKey {
+        self.insert_with(|_| item)
+    }
+
+    /// Insert an item built from the key it is about to be stored under
+    ///
+    /// The key is worked out before `f` is called, so the item can hold its own key.
+    /// Nothing is changed until `f` returns, so a panic in it leaves the SlotMap as it was
+    pub fn insert_with<F>(&mut self, f: F) -> SlotKey
+    where
+        F: FnOnce(SlotKey) -> T,
+    {
+        // Check if there is an slot we can insert into
+        let free_index = (self.item_count < self.slots.len()).then(|| {
+            // There is a slot somewhere
+            self.find_free_slot().expect(
+                "There must be a free slot, otherwise we are keeping item_count out of sync",
+            )
+        });
+        let insert_index = free_index.unwrap_or(self.slots.len());
+
+        let item = f(SlotKey::new(insert_index));
+        self.touch();
+
+        if free_index.is_some() {
+            // Update the slot to store this item
+            self.slots[insert_index].set(item);
+
+            // Mark the slot as full again so it is not handed out twice
+            let (slot_chunk, slot_mask) = bit_position::<C>(insert_index);
+            self.empty_indexes[slot_chunk] |= slot_mask;
+        } else {
+            // Just insert the item, as there is no open space
+            self.slots.push(Slot::new(item));
+        }
+
+        // Increment item_count
+        self.item_count += 1;
+
+        // Create an index referring to this item
+        SlotKey::new(insert_index)
+    }
+
+    /// Remove an item from the SlotMap
+    pub fn remove(&mut self, slot_key: SlotKey) -> T {
+        let slot = self
+            .slots
+            .get_mut(slot_key.index)
+            .expect("Index should be in range as SlotMap never shrinks");
+
+        // Take the item before the bookkeeping, so a key to an empty slot panics
+        // without leaving item_count and the bitmap out of sync
+        let item = slot.clear().expect("Key exists so should data");
+        self.item_count -= 1;
+        self.touch();
+
+        // Locate the bit we must unset
+        let (slot_chunk, slot_mask) = bit_position::<C>(slot_key.index);
+
+        // We now need to set a free bit, but if the chunks have not been generated we must generate them
+        if self.empty_indexes.len() <= slot_chunk {
+            // If we haven't removed any elements from these chunks of elements then they must all be full
+            // or outside the range of the SlotMap
+            // `bit_position` keeps the chunk index well below `usize::MAX`, so this cannot overflow
+            self.empty_indexes.resize(slot_chunk + 1, C::FULL);
+        }
+        debug_assert!(
+            slot_chunk < self.empty_indexes.len(),
+            "The bitmap should reach slot {} after growing",
+            slot_key.index
+        );
+
+        // Invert the mask so we can use AND to unset the bit
+        self.empty_indexes[slot_chunk] &= !slot_mask;
+
+        item
+    }
+
+    /// Remove an item and fill its slot with the item in the highest occupied slot
+    ///
+    /// Returns the removed item along with the old key of the item that was moved, if
+    /// one came from a higher slot, as that item now lives under `slot_key`. The
+    /// caller must update anything holding the old key. This keeps the occupied slots
+    /// packed at the front one removal at a time, without rebuilding the SlotMap.
+    /// Returns `None` if the key does not point at an item
+    ///
+    /// # NOTE
+    ///
+    /// Finding the highest occupied slot walks back over any empty slots at the end
+    pub fn swap_remove(&mut self, slot_key: SlotKey) -> Option<(T, Option<SlotKey>)> {
+        self.try_get(slot_key)?;
+        let item = self.remove(slot_key);
+
+        let last_index = self
+            .slots
+            .iter()
+            .rposition(|slot| slot.item.is_some())
+            .filter(|&last_index| last_index > slot_key.index);
+        let Some(last_index) = last_index else {
+            return Some((item, None));
+        };
+
+        let moved_key = SlotKey::new(last_index);
+        let moved = self.remove(moved_key);
+
+        // Fill the freed slot and mark it full again, as `insert_with` does
+        self.slots[slot_key.index].set(moved);
+        let (slot_chunk, slot_mask) = bit_position::<C>(slot_key.index);
+        self.empty_indexes[slot_chunk] |= slot_mask;
+        self.item_count += 1;
+
+        Some((item, Some(moved_key)))
+    }
+
+    /// Remove every item from the SlotMap, keeping the allocated space for reuse
+    pub fn clear(&mut self) {
+        self.touch();
+        self.slots.clear();
+        self.item_count = 0;
+        self.empty_indexes.clear();
+    }
+
+    /// Remove every item from the SlotMap, yielding each one in slot order
+    ///
+    /// The SlotMap is empty as soon as this is called, and any items not yet
+    /// yielded are dropped along with the iterator. The allocated space is kept for reuse
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.touch();
+        self.item_count = 0;
+        self.empty_indexes.clear();
+
+        self.slots.drain(..).filter_map(|slot| slot.item)
+    }
+
+    /// Walk every slot in index order, giving `None` for the empty ones
+    ///
+    /// This shows the raw layout of the SlotMap, for spotting fragmentation
+    pub fn slots_debug(&self) -> impl Iterator<Item = (usize, Option<&T>)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .map(|(index, slot)| (index, slot.item.as_ref()))
+    }
+
+    /// Walk every item along with its key, in slot order
+    pub fn iter(&self) -> impl Iterator<Item = (SlotKey, &T)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| slot.item.as_ref().map(|item| (SlotKey::new(index), item)))
+    }
+
+    /// Walk every item in slot order, skipping empty slots
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter().filter_map(|slot| slot.item.as_ref())
+    }
+
+    /// Walk mutable references to every item in slot order, skipping empty slots
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.touch();
+        self.slots.iter_mut().filter_map(|slot| slot.item.as_mut())
+    }
+
+    /// Get a reference to an item from the SlotMap
+    pub fn get(&self, slot_key: SlotKey) -> &T {
+        let slot = self
+            .slots
+            .get(slot_key.index)
+            .expect("Index should be in range as SlotMap never decreases in length");
+
+        // This should never return None, as the key cannot be copied and the key only gets used on removal
+        slot.item.as_ref().expect("Should exist as key exists")
+    }
+
+    /// Get a mutable reference to an item from the SlotMap
+    pub fn get_mut(&mut self, slot_key: SlotKey) -> &mut T {
+        self.touch();
+        let slot = self
+            .slots
+            .get_mut(slot_key.index)
+            .expect("Index should be in range as SlotMap never decreases in length");
+
+        // This should also never return None, for the reason above
+        slot.item.as_mut().expect("Should exist as key exists")
+    }
+
+    /// Get a reference to an item without checking the key is in range or the slot is full
+    ///
+    /// This is for hot loops where profiling shows the checks in `get` matter, and the
+    /// key is already known to be good. Debug builds still check it. The
+    /// `get_unchecked` bench compares the two
+    ///
+    /// # Safety
+    ///
+    /// The key must point at an occupied slot, such as a key taken from a live node
+    /// that has not been removed since. Any other key is undefined behaviour
+    pub unsafe fn get_unchecked(&self, slot_key: SlotKey) -> &T {
+        debug_assert!(
+            self.try_get(slot_key).is_some(),
+            "The key must point at an item"
+        );
+
+        // SAFETY: The caller guarantees the index is in range and the slot is full
+        unsafe {
+            self.slots
+                .get_unchecked(slot_key.index)
+                .item
+                .as_ref()
+                .unwrap_unchecked()
+        }
+    }
+
+    /// Get a mutable reference to an item without checking the key is in range or the
+    /// slot is full
+    ///
+    /// # Safety
+    ///
+    /// The key must point at an occupied slot, the same as for `get_unchecked`
+    pub unsafe fn get_unchecked_mut(&mut self, slot_key: SlotKey) -> &mut T {
+        debug_assert!(
+            self.try_get(slot_key).is_some(),
+            "The key must point at an item"
+        );
+
+        self.touch();
+
+        // SAFETY: The caller guarantees the index is in range and the slot is full
+        unsafe {
+            self.slots
+                .get_unchecked_mut(slot_key.index)
+                .item
+                .as_mut()
+                .unwrap_unchecked()
+        }
+    }
+
+    /// Get a reference to an item, or `None` if the key does not point at one
+    pub fn try_get(&self, slot_key: SlotKey) -> Option<&T> {
+        self.slots.get(slot_key.index)?.item.as_ref()
+    }
+
+    /// Get a mutable reference to an item, or `None` if the key does not point at one
+    pub fn try_get_mut(&mut self, slot_key: SlotKey) -> Option<&mut T> {
+        self.touch();
+        self.slots.get_mut(slot_key.index)?.item.as_mut()
+    }
+
+    /// Get mutable references to several items at once
+    ///
+    /// Gives `None` if any key is repeated or does not point at an item, as the
+    /// references would otherwise alias
+    pub fn get_disjoint_mut<const N: usize>(&mut self, keys: [SlotKey; N]) -> Option<[&mut T; N]> {
+        let occupied = |key: &SlotKey| {
+            self.slots
+                .get(key.index)
+                .is_some_and(|slot| slot.item.is_some())
+        };
+        if !keys.iter().all(occupied) {
+            return None;
+        }
+        self.touch();
+
+        // The slice check rejects overlapping indexes, so every slot is borrowed once
+        let slots = self
+            .slots
+            .get_disjoint_mut(keys.map(|key| key.index))
+            .ok()?;
+
+        Some(slots.map(|slot| {
+            slot.item
+                .as_mut()
+                .expect("Checked that every slot is occupied")
+        }))
+    }
+
+    /// Overwrite `dst` with a copy of this SlotMap, mapping each item through `f`
+    ///
+    /// Every item lands in the same slot, so keys stay valid between the two,
+    /// and `dst` reuses its existing allocations where it can
+    pub(crate) fn map_into<U, F>(&self, dst: &mut SlotMap<U, C>, mut f: F)
+    where
+        F: FnMut(&T) -> U,
+    {
+        dst.touch();
+        dst.slots.clear();
+        dst.slots.extend(self.slots.iter().map(|slot| Slot {
+            item: slot.item.as_ref().map(&mut f),
+        }));
+        dst.item_count = self.item_count;
+        dst.empty_indexes.clone_from(&self.empty_indexes);
+    }
+
+    /// Swap the item a key points at for a new one, returning the old item
+    ///
+    /// The slot stays occupied, so the key and the free slot bitmap are unchanged
+    pub fn replace(&mut self, slot_key: SlotKey, item: T) -> T {
+        core::mem::replace(self.get_mut(slot_key), item)
+    }
+
+    /// Check if the bitmap marks a slot as full
+    ///
+    /// Chunks that have not been generated yet are treated as entirely full
+    fn marked_full(&self, index: usize) -> bool {
+        let (slot_chunk, slot_mask) = bit_position::<C>(index);
+
+        let Some(&chunk) = self.empty_indexes.get(slot_chunk) else {
+            return true;
+        };
+
+        chunk & slot_mask != C::EMPTY
+    }
+
+    /// Check the bookkeeping agrees with the slots themselves
+    ///
+    /// `item_count` must equal the number of occupied slots, and each slot's bit must be
+    /// set exactly when it is occupied. Bits past the last slot must be set too, otherwise
+    /// `find_free_slot` could hand out a slot that does not exist
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let occupied = self.slots.iter().filter(|slot| slot.item.is_some()).count();
+
+        if occupied != self.item_count {
+            return Err(format!(
+                "item_count is {} but {} slots are occupied",
+                self.item_count, occupied
+            ));
+        }
+
+        for (index, slot) in self.slots.iter().enumerate() {
+            let full = slot.item.is_some();
+
+            if self.marked_full(index) != full {
+                return Err(format!(
+                    "Slot {} is {} but its bit says it is {}",
+                    index,
+                    if full { "occupied" } else { "empty" },
+                    if full { "empty" } else { "occupied" },
+                ));
+            }
+        }
+
+        let generated = self.empty_indexes.len().saturating_mul(C::BITS as usize);
+        if let Some(index) = (self.slots.len()..generated).find(|&index| !self.marked_full(index)) {
+            return Err(format!(
+                "Slot {} is past the end of the SlotMap but its bit says it is free",
+                index
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Recalculate `item_count` and the bitmap from the slots themselves
+    ///
+    /// Afterwards `check_invariants` always succeeds
+    pub fn repair(&mut self) {
+        self.item_count = self.slots.iter().filter(|slot| slot.item.is_some()).count();
+
+        let bit_length = C::BITS as usize;
+        self.empty_indexes = vec![C::FULL; self.slots.len().div_ceil(bit_length)];
+
+        for (index, slot) in self.slots.iter().enumerate() {
+            if slot.item.is_none() {
+                let (slot_chunk, slot_mask) = bit_position::<C>(index);
+                self.empty_indexes[slot_chunk] &= !slot_mask;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fill past two chunks of `u64`, then remove and reinsert at each chunk boundary
+    fn boundaries_round_trip<C>()
+    where
+        C: BitChunk,
+    {
+        let mut slots = SlotMap::<usize, C>::default();
+        let keys: Vec<SlotKey> = (0..200).map(|index| slots.insert(index)).collect();
+        slots.check_invariants().unwrap();
+
+        for index in [0, 31, 32, 63, 64, 127, 128, 199] {
+            assert_eq!(slots.remove(keys[index]), index);
+            slots.check_invariants().unwrap();
+            assert_eq!(slots.find_free_slot(), Some(index));
+
+            assert_eq!(slots.insert(index), keys[index]);
+            slots.check_invariants().unwrap();
+        }
+
+        // With several slots free at once, the lowest is always handed out first
+        for index in [128, 64, 127, 63] {
+            slots.remove(keys[index]);
+        }
+        slots.check_invariants().unwrap();
+        for index in [63, 64, 127, 128] {
+            assert_eq!(slots.insert(index), keys[index]);
+            slots.check_invariants().unwrap();
+        }
+
+        assert_eq!(slots.item_count, 200);
+        assert!(
+            keys.iter()
+                .enumerate()
+                .all(|(index, &key)| slots[key] == index)
+        );
+    }
+
+    #[test]
+    fn chunk_boundaries_u64() {
+        boundaries_round_trip::<u64>();
+    }
+
+    #[test]
+    fn chunk_boundaries_u32() {
+        boundaries_round_trip::<u32>();
+    }
+
+    #[test]
+    fn chunk_boundaries_u128() {
+        boundaries_round_trip::<u128>();
+    }
+
+    #[test]
+    fn slot_index_overflow_near_usize_max() {
+        // With 8 bit chunks the last chunk starts at usize::MAX - 7
+        let last_chunk = usize::MAX / 8;
+        assert_eq!(slot_index::<u8>(last_chunk, 0), Some(usize::MAX - 7));
+        assert_eq!(slot_index::<u8>(last_chunk, 7), Some(usize::MAX));
+        assert_eq!(slot_index::<u8>(last_chunk + 1, 0), None);
+        assert_eq!(slot_index::<u8>(usize::MAX, 0), None);
+
+        // The u64 equivalent near usize::MAX / 64
+        let last_chunk = usize::MAX / 64;
+        assert_eq!(slot_index::<u64>(last_chunk, 63), Some(usize::MAX));
+        assert_eq!(slot_index::<u64>(last_chunk + 1, 0), None);
+    }
+
+    #[test]
+    fn bit_position_round_trips_at_usize_max() {
+        for index in [0, 7, 8, usize::MAX - 8, usize::MAX - 7, usize::MAX] {
+            let (chunk_index, mask) = bit_position::<u8>(index);
+            let bit_offset = mask.leading_zeros();
+            assert_eq!(slot_index::<u8>(chunk_index, bit_offset), Some(index));
+        }
+    }
+}
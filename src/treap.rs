@@ -0,0 +1,236 @@
+use alloc::vec::Vec;
+use core::fmt::Debug;
+#[cfg(feature = "std")]
+use std::{collections::hash_map::RandomState, hash::BuildHasher};
+
+use crate::{
+    rotation::{self, BinaryNode},
+    slotmap::{SlotKey, SlotMap},
+    tree::TreeDirection,
+};
+
+/// Used in place of a zero seed, which would leave the generator stuck at zero
+const FALLBACK_SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+
+struct TreapNode<T> {
+    data: T,
+    priority: u64,
+    left: Option<SlotKey>,
+    right: Option<SlotKey>,
+}
+
+impl<T> Debug for TreapNode<T>
+where
+    T: Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TreapNode")
+            .field("data", &self.data)
+            .field("priority", &self.priority)
+            .field("left", &self.left)
+            .field("right", &self.right)
+            .finish()
+    }
+}
+
+impl<T> TreapNode<T> {
+    fn new(data: T, priority: u64) -> Self {
+        Self {
+            data,
+            priority,
+            left: None,
+            right: None,
+        }
+    }
+}
+
+impl<T> BinaryNode for TreapNode<T> {
+    type Data = T;
+
+    fn data(&self) -> &T {
+        &self.data
+    }
+
+    fn child(&self, direction: TreeDirection) -> Option<SlotKey> {
+        match direction {
+            TreeDirection::Left => self.left,
+            TreeDirection::Right => self.right,
+        }
+    }
+
+    fn set_child(&mut self, direction: TreeDirection, child: Option<SlotKey>) {
+        match direction {
+            TreeDirection::Left => self.left = child,
+            TreeDirection::Right => self.right = child,
+        }
+    }
+}
+
+/// A BST that balances itself by giving every node a random priority
+///
+/// Values are kept in BST order while priorities are kept in heap order (every parent
+/// outranks its children), which makes the shape the same as if the values had been
+/// inserted in a random order. The expected height is therefore O(log n) whatever
+/// order values arrive in, without having to store any balance information
+pub struct Treap<T> {
+    storage: SlotMap<TreapNode<T>>,
+    /// `None` when the treap is empty
+    root: Option<SlotKey>,
+    /// State of the xorshift generator handing out priorities
+    rng_state: u64,
+}
+
+impl<T> Debug for Treap<T>
+where
+    T: Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Treap")
+            .field("storage", &self.storage)
+            .field("root", &self.root)
+            .field("rng_state", &self.rng_state)
+            .finish()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Default for Treap<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Treap<T> {
+    /// Create an empty treap with a randomly chosen seed
+    ///
+    /// Needs the `std` feature for a source of randomness, otherwise use `with_seed`
+    #[cfg(feature = "std")]
+    pub fn new() -> Self {
+        Self::with_seed(RandomState::new().hash_one(0_u8))
+    }
+
+    /// Create an empty treap whose priorities come from `seed`
+    ///
+    /// The same seed and the same operations always produce the same shape,
+    /// which makes the treap deterministic for testing
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            storage: SlotMap::new(),
+            root: None,
+            rng_state: if seed == 0 { FALLBACK_SEED } else { seed },
+        }
+    }
+
+    /// Count the values in the treap
+    pub fn len(&self) -> usize {
+        self.storage.item_count
+    }
+
+    /// Check if the treap has no values
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Generate the priority for the next node, using xorshift64*
+    fn next_priority(&mut self) -> u64 {
+        let mut x = self.rng_state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng_state = x;
+
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Point a parent's child, or the root if there is no parent, at a node
+    fn link(&mut self, parent: Option<(SlotKey, TreeDirection)>, child: Option<SlotKey>) {
+        rotation::link(&mut self.storage, &mut self.root, parent, child);
+    }
+
+    /// Get the number of nodes on the longest path from the root to a leaf
+    pub fn height(&self) -> usize {
+        rotation::height(&self.storage, self.root)
+    }
+
+    /// Collect references to every value in ascending order
+    pub fn to_vec(&self) -> Vec<&T> {
+        rotation::to_vec(&self.storage, self.root)
+    }
+}
+
+impl<T> Treap<T>
+where
+    T: Eq + Ord + Debug,
+{
+    /// Insert data into the treap
+    ///
+    /// # NOTE
+    ///
+    /// If the data already exists in the treap, then it just returns the data
+    pub fn insert(&mut self, data: T) -> Result<(), T> {
+        // The nodes passed on the way down, and which way we went at each
+        let (mut path, found) = rotation::descend(&self.storage, self.root, &data);
+        if found.is_some() {
+            // We cannot accept duplicates
+            return Err(data);
+        }
+
+        // Insert as a leaf, which keeps BST order
+        let priority = self.next_priority();
+        let new_key = self.storage.insert(TreapNode::new(data, priority));
+        self.link(path.last().copied(), Some(new_key));
+
+        // Restore heap order by rotating the new node up past every parent it outranks
+        while let Some((parent_key, direction)) = path.pop() {
+            if self.storage.get(parent_key).priority >= priority {
+                break;
+            }
+
+            let lifted = rotation::rotate(&mut self.storage, parent_key, direction.opposite());
+            self.link(path.last().copied(), Some(lifted));
+        }
+
+        Ok(())
+    }
+
+    /// Check if treap contains
+    pub fn contains(&self, data: &T) -> bool {
+        rotation::contains(&self.storage, self.root, data)
+    }
+
+    /// Remove data from the treap, returning it if it existed
+    pub fn remove(&mut self, data: &T) -> Option<T> {
+        // Locate the node holding the data
+        let (path, found) = rotation::descend(&self.storage, self.root, data);
+        let node_key = found?;
+        let mut parent = path.last().copied();
+
+        // Rotate the node down until it has at most one child, at which point it can be spliced out
+        loop {
+            let node = self.storage.get(node_key);
+
+            match (node.left, node.right) {
+                (Some(left), Some(right)) => {
+                    // Lift whichever child has the higher priority, so heap order holds above the node
+                    let lift = if self.storage.get(left).priority > self.storage.get(right).priority
+                    {
+                        TreeDirection::Left
+                    } else {
+                        TreeDirection::Right
+                    };
+
+                    let lifted = rotation::rotate(&mut self.storage, node_key, lift.opposite());
+                    self.link(parent, Some(lifted));
+
+                    // The node now hangs off the other side of the child that was lifted
+                    parent = Some((lifted, lift.opposite()));
+                }
+                (child, None) | (None, child) => {
+                    self.link(parent, child);
+
+                    return Some(self.storage.remove(node_key).data);
+                }
+            }
+        }
+    }
+}
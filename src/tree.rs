@@ -0,0 +1,5912 @@
+use alloc::{
+    collections::{BinaryHeap, VecDeque},
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{
+    cmp::{Ordering, Reverse},
+    fmt::{Debug, Display},
+    hash::{Hash, Hasher},
+};
+
+use crate::{
+    bytes::ByteCodec,
+    json,
+    rotation::{self, BinaryNode},
+    slotmap::{SlotKey, SlotMap},
+};
+
+/// Version written at the start of `Tree::to_bytes`, bumped whenever the layout changes
+const BYTES_FORMAT_VERSION: u8 = 1;
+
+pub struct TreeNode<T> {
+    data: T,
+    left: Option<SlotKey>,
+    right: Option<SlotKey>,
+    /// `None` for the root
+    parent: Option<SlotKey>,
+    /// Number of nodes in the subtree rooted here, including this one
+    size: usize,
+}
+
+impl<T> Debug for TreeNode<T>
+where
+    T: Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TreeNode")
+            .field("data", &self.data)
+            .field("left", &self.left)
+            .field("right", &self.right)
+            .field("parent", &self.parent)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+impl<T> TreeNode<T> {
+    fn new(data: T) -> Self {
+        Self {
+            data,
+            left: None,
+            right: None,
+            parent: None,
+            size: 1,
+        }
+    }
+
+    /// Get the value stored in this node
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+
+    /// Get the key of the left child
+    pub fn left(&self) -> Option<SlotKey> {
+        self.left
+    }
+
+    /// Get the key of the right child
+    pub fn right(&self) -> Option<SlotKey> {
+        self.right
+    }
+
+    /// Get the key of the parent, which is `None` for the root
+    pub fn parent(&self) -> Option<SlotKey> {
+        self.parent
+    }
+}
+
+impl<T> BinaryNode for TreeNode<T> {
+    type Data = T;
+
+    fn data(&self) -> &T {
+        &self.data
+    }
+
+    fn child(&self, direction: TreeDirection) -> Option<SlotKey> {
+        match direction {
+            TreeDirection::Left => self.left,
+            TreeDirection::Right => self.right,
+        }
+    }
+
+    fn set_child(&mut self, direction: TreeDirection, child: Option<SlotKey>) {
+        match direction {
+            TreeDirection::Left => self.left = child,
+            TreeDirection::Right => self.right = child,
+        }
+    }
+
+    fn set_parent(&mut self, parent: Option<SlotKey>) {
+        self.parent = parent;
+    }
+}
+
+/// Get the size of a subtree, where a missing subtree is empty
+fn subtree_size<T>(storage: &SlotMap<TreeNode<T>>, subtree_root: Option<SlotKey>) -> usize {
+    subtree_root.map_or(0, |subtree_root| storage.get(subtree_root).size)
+}
+
+/// Point a node's child on one side at another node, and that node's parent back at it
+fn attach<T>(
+    storage: &mut SlotMap<TreeNode<T>>,
+    parent_key: SlotKey,
+    direction: TreeDirection,
+    child: Option<SlotKey>,
+) {
+    storage.get_mut(parent_key).set_child(direction, child);
+
+    if let Some(child_key) = child {
+        storage.get_mut(child_key).parent = Some(parent_key);
+    }
+}
+
+/// Write a Newick label, quoting it if it holds anything that would be read as structure
+///
+/// Inside quotes a `'` is written twice, as the format requires
+fn write_newick_label(out: &mut String, label: &str) {
+    let needs_quotes = label
+        .chars()
+        .any(|c| c.is_whitespace() || "()[]':;,".contains(c));
+
+    if !needs_quotes {
+        out.push_str(label);
+        return;
+    }
+
+    out.push('\'');
+    for c in label.chars() {
+        if c == '\'' {
+            out.push('\'');
+        }
+        out.push(c);
+    }
+    out.push('\'');
+}
+
+/// Move the subtree rooted at `subtree_root` out of one storage and into another
+///
+/// The subtree must already be detached from any parent, and the key of its new root is returned
+fn move_subtree<T>(
+    from: &mut SlotMap<TreeNode<T>>,
+    subtree_root: SlotKey,
+    to: &mut SlotMap<TreeNode<T>>,
+) -> SlotKey {
+    // Shapes are kept exactly, so sizes carry across unchanged
+    let root_node = from.remove(subtree_root);
+    let children = (root_node.left, root_node.right);
+    let new_root = to.insert(TreeNode {
+        size: root_node.size,
+        ..TreeNode::new(root_node.data)
+    });
+
+    // Each entry is a node still to move, and the moved parent it belongs under
+    let mut stack = Vec::new();
+    push_children(&mut stack, children, new_root);
+
+    while let Some((old_key, new_parent, direction)) = stack.pop() {
+        let old_node = from.remove(old_key);
+        let children = (old_node.left, old_node.right);
+        let new_key = to.insert(TreeNode {
+            size: old_node.size,
+            ..TreeNode::new(old_node.data)
+        });
+
+        attach(to, new_parent, direction, Some(new_key));
+
+        push_children(&mut stack, children, new_key);
+    }
+
+    new_root
+}
+
+/// Panic in debug builds if `Ord` and `PartialEq` disagree about two values, or `cmp` contradicts itself
+///
+/// A broken ordering otherwise sends values down the wrong side of the tree, where
+/// later searches will not find them
+fn debug_check_ordering<T>(new: &T, existing: &T)
+where
+    T: Ord + Debug,
+{
+    let ordering = new.cmp(existing);
+
+    debug_assert_eq!(
+        ordering == Ordering::Equal,
+        new == existing,
+        "Ord and == disagree about {:?} and {:?}, so Ord is not a total order consistent with Eq",
+        new,
+        existing
+    );
+    debug_assert_eq!(
+        existing.cmp(new),
+        ordering.reverse(),
+        "Comparing {:?} and {:?} in both directions gives contradictory results",
+        new,
+        existing
+    );
+    debug_assert_eq!(
+        new < existing,
+        ordering == Ordering::Less,
+        "< and cmp disagree about {:?} and {:?}",
+        new,
+        existing
+    );
+}
+
+/// Queue the children of a node that is being copied, so they get attached under `new_parent`
+fn push_children(
+    stack: &mut Vec<(SlotKey, SlotKey, TreeDirection)>,
+    (left, right): (Option<SlotKey>, Option<SlotKey>),
+    new_parent: SlotKey,
+) {
+    // Right is pushed first so the left side is copied first
+    if let Some(right) = right {
+        stack.push((right, new_parent, TreeDirection::Right));
+    }
+    if let Some(left) = left {
+        stack.push((left, new_parent, TreeDirection::Left));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeDirection {
+    Left,
+    Right,
+}
+
+impl TreeDirection {
+    /// Get the other direction
+    pub fn opposite(self) -> Self {
+        match self {
+            TreeDirection::Left => TreeDirection::Right,
+            TreeDirection::Right => TreeDirection::Left,
+        }
+    }
+}
+
+/// What `Tree::walk_pruned` does after visiting a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkControl {
+    /// Carry on into the node's children
+    Continue,
+    /// Leave out the node's subtrees, but carry on with the rest of the walk
+    SkipChildren,
+    /// End the walk here
+    Stop,
+}
+
+/// Where a value was found by `Tree::merge_join`
+#[derive(Debug, PartialEq, Eq)]
+pub enum MergeSide<'a, T> {
+    /// Only in the tree `merge_join` was called on
+    Left(&'a T),
+    /// Only in the other tree
+    Right(&'a T),
+    /// In both trees, this tree's value first
+    Both(&'a T, &'a T),
+}
+
+impl<T> Clone for MergeSide<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for MergeSide<'_, T> {}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TreeOrdering {
+    /// NLR
+    Pre,
+    /// LNR
+    In,
+    /// LRN
+    Post,
+}
+
+impl TreeOrdering {
+    /// Every ordering, in the order they are usually printed
+    pub const ALL: [TreeOrdering; 3] = [TreeOrdering::Pre, TreeOrdering::In, TreeOrdering::Post];
+
+    /// Get the name of the ordering, which `FromStr` parses back
+    pub fn name(&self) -> &'static str {
+        match self {
+            TreeOrdering::Pre => "preorder",
+            TreeOrdering::In => "inorder",
+            TreeOrdering::Post => "postorder",
+        }
+    }
+}
+
+/// The text given to `TreeOrdering::from_str` was not the name of an ordering
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseTreeOrderingError;
+
+impl Display for ParseTreeOrderingError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("expected one of preorder, inorder or postorder")
+    }
+}
+
+impl core::error::Error for ParseTreeOrderingError {}
+
+/// Parses the names given by `TreeOrdering::name`, ignoring case
+impl core::str::FromStr for TreeOrdering {
+    type Err = ParseTreeOrderingError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        TreeOrdering::ALL
+            .into_iter()
+            .find(|ordering| ordering.name().eq_ignore_ascii_case(text))
+            .ok_or(ParseTreeOrderingError)
+    }
+}
+
+/// What inserting a value does when an equal value is already in the tree
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Leave the stored value and hand the new one back as an error
+    #[default]
+    Reject,
+    /// Store the new value and hand the old one back as an error
+    Replace,
+    /// Leave the stored value, drop the new one and report success
+    Ignore,
+}
+
+/// Why a value could not be added to the tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InsertError<T> {
+    /// An equal value is already in the tree, and this is whichever of the two the
+    /// duplicate policy left out
+    Duplicate(T),
+    /// The tree already holds its maximum number of values, and this is the new data
+    Full(T),
+    /// The new data could not be compared with a value in the tree, so it has no place
+    Incomparable(T),
+    /// Adding the new data would take the tree past its maximum height
+    TooDeep(T),
+}
+
+impl<T> InsertError<T> {
+    /// Get back the value that was left out of the tree
+    pub fn into_inner(self) -> T {
+        match self {
+            InsertError::Duplicate(data)
+            | InsertError::Full(data)
+            | InsertError::Incomparable(data)
+            | InsertError::TooDeep(data) => data,
+        }
+    }
+}
+
+impl<T> Display for InsertError<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            InsertError::Duplicate(_) => f.write_str("an equal value is already in the tree"),
+            InsertError::Full(_) => f.write_str("the tree is at its maximum length"),
+            InsertError::Incomparable(_) => {
+                f.write_str("the value cannot be compared with one in the tree")
+            }
+            InsertError::TooDeep(_) => f.write_str("the tree is at its maximum height"),
+        }
+    }
+}
+
+impl<T> core::error::Error for InsertError<T> where T: Debug {}
+
+/// Why a lookup in the tree failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeError {
+    /// No equal value is in the tree, and this is the `Debug` form of the one searched for
+    NotFound(String),
+}
+
+impl Display for TreeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TreeError::NotFound(data) => write!(f, "{} is not in the tree", data),
+        }
+    }
+}
+
+impl core::error::Error for TreeError {}
+
+/// Why `Tree::swap_values` left two nodes as they were
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapError {
+    /// One of the keys does not point at a node
+    MissingKey(SlotKey),
+    /// Swapping would put a value out of order with one of its neighbours
+    OutOfOrder,
+}
+
+impl Display for SwapError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SwapError::MissingKey(key) => write!(f, "{:?} does not point at a node", key),
+            SwapError::OutOfOrder => f.write_str("swapping would break the tree's ordering"),
+        }
+    }
+}
+
+impl core::error::Error for SwapError {}
+
+/// Why `Tree::from_bracket_str` could not read a listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The listing has an opening or closing bracket without the other
+    UnmatchedBracket,
+    /// An entry at this position, counting from zero, is neither `null` nor a value
+    BadToken { index: usize, token: String },
+    /// A value at this position has no parent to hang from, as every node that could
+    /// have held it is missing
+    Orphan(usize),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ParseError::UnmatchedBracket => {
+                f.write_str("the brackets around the listing do not match")
+            }
+            ParseError::BadToken { index, token } => {
+                write!(
+                    f,
+                    "entry {} is {:?}, which is neither null nor a value",
+                    index, token
+                )
+            }
+            ParseError::Orphan(index) => write!(f, "entry {} has no parent to hang from", index),
+        }
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+/// Why `Tree::rotate_at` left the tree as it was
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotateError {
+    /// No equal value is in the tree
+    NotFound,
+    /// The node has no child on the side the rotation would lift
+    MissingChild,
+}
+
+impl Display for RotateError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RotateError::NotFound => f.write_str("the value is not in the tree"),
+            RotateError::MissingChild => f.write_str("the node has no child to lift"),
+        }
+    }
+}
+
+impl core::error::Error for RotateError {}
+
+/// What happened to a value given to `Tree::try_insert`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertOutcome {
+    /// The value was added in a new node with this key
+    Inserted(SlotKey),
+    /// An equal value was already in the tree, so no node was added
+    Duplicate,
+    /// The tree already holds its maximum number of values
+    Full,
+    /// A new node would have gone past the tree's maximum height
+    TooDeep,
+}
+
+/// A summary of the shape of a tree, from `Tree::stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeStats {
+    /// Number of nodes
+    pub node_count: usize,
+    /// Number of nodes on the longest path from the root to a leaf
+    pub height: usize,
+    /// Depth of the shallowest leaf, where the root is at depth 0
+    pub min_depth: usize,
+    /// Number of nodes with no children
+    pub leaf_count: usize,
+    /// Number of nodes with at least one child
+    pub internal_count: usize,
+    /// Whether the heights of the two subtrees of every node differ by at most one
+    pub balanced: bool,
+}
+
+/// A custom ordering and equality given to `Tree::new_with`
+struct Comparator<T> {
+    cmp: fn(&T, &T) -> Ordering,
+    eq: fn(&T, &T) -> bool,
+}
+
+impl<T> Clone for Comparator<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Comparator<T> {}
+
+/// Options chosen when building a tree, carried over to the trees split off from it
+struct TreeSettings<T> {
+    duplicate_policy: DuplicatePolicy,
+    /// Inserts that would add a node beyond this many are refused
+    max_len: Option<usize>,
+    /// Inserts that would add a node beyond this many levels are refused
+    max_height: Option<usize>,
+    /// Storage is compacted after a removal leaves fewer live nodes than this share of its slots
+    auto_compact: Option<f64>,
+    /// The tree is rebalanced after an insert leaves it more than this many times its
+    /// smallest possible height
+    rebalance_factor: Option<f64>,
+    /// `None` uses `Ord` and `Eq`
+    comparator: Option<Comparator<T>>,
+}
+
+impl<T> Debug for TreeSettings<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TreeSettings")
+            .field("duplicate_policy", &self.duplicate_policy)
+            .field("max_len", &self.max_len)
+            .field("max_height", &self.max_height)
+            .field("auto_compact", &self.auto_compact)
+            .field("rebalance_factor", &self.rebalance_factor)
+            .field("custom_comparator", &self.comparator.is_some())
+            .finish()
+    }
+}
+
+impl<T> Clone for TreeSettings<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for TreeSettings<T> {}
+
+impl<T> Default for TreeSettings<T> {
+    fn default() -> Self {
+        Self {
+            duplicate_policy: DuplicatePolicy::default(),
+            max_len: None,
+            max_height: None,
+            auto_compact: None,
+            rebalance_factor: None,
+            comparator: None,
+        }
+    }
+}
+
+impl<T> TreeSettings<T>
+where
+    T: Ord + Debug,
+{
+    /// Order data against a node's data, with the custom comparator if there is one
+    ///
+    /// Values the comparator's `eq` accepts are `Equal`, otherwise anything `cmp` does
+    /// not call `Less` is `Greater`, matching the way `search_step` goes
+    fn compare(&self, data: &T, node_data: &T) -> Ordering {
+        match self.comparator {
+            Some(Comparator { cmp, eq }) => {
+                if eq(data, node_data) {
+                    Ordering::Equal
+                } else if cmp(data, node_data) == Ordering::Less {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            }
+            None => {
+                debug_check_ordering(data, node_data);
+
+                data.cmp(node_data)
+            }
+        }
+    }
+
+    /// Decide which way to go from a node when searching for data, or `None` if
+    /// the node holds it
+    fn search_step(&self, data: &T, node_data: &T) -> Option<TreeDirection> {
+        match self.compare(data, node_data) {
+            Ordering::Equal => None,
+            Ordering::Less => Some(TreeDirection::Left),
+            Ordering::Greater => Some(TreeDirection::Right),
+        }
+    }
+}
+
+pub struct Tree<T> {
+    storage: SlotMap<TreeNode<T>>,
+    /// `None` when the tree is empty
+    root: Option<SlotKey>,
+    settings: TreeSettings<T>,
+    /// Filled by `sorted_cache`, and only trusted while the storage is unchanged
+    sorted_cache: Option<SortedCache>,
+}
+
+/// The in order keys of a tree, with what the tree looked like when they were taken
+struct SortedCache {
+    /// The storage's change count at the time
+    changes: u64,
+    root: Option<SlotKey>,
+    keys: Vec<SlotKey>,
+}
+
+impl<T> Tree<T> {
+    /// A rebalance factor for `with_rebalance_factor` that keeps the height within
+    /// about twice the best
+    pub const DEFAULT_REBALANCE_FACTOR: f64 = 2.0;
+
+    pub fn new(root: T) -> Self {
+        let mut storage = SlotMap::new();
+
+        let root = storage.insert(TreeNode::new(root));
+
+        Self {
+            storage,
+            root: Some(root),
+            settings: TreeSettings::default(),
+            sorted_cache: None,
+        }
+    }
+
+    /// Create a tree with no nodes
+    pub fn empty() -> Self {
+        Self {
+            storage: SlotMap::new(),
+            root: None,
+            settings: TreeSettings::default(),
+            sorted_cache: None,
+        }
+    }
+
+    /// Create a tree with no nodes and room for `capacity` of them before the storage
+    /// has to reallocate
+    ///
+    /// `from_sorted` and `from_sorted_iter` already reserve exactly what they need, so
+    /// this is for filling a tree of known size some other way
+    ///
+    /// # NOTE
+    ///
+    /// `capacity()` counts slots in use or freed, so it stays zero until nodes are added
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            storage: SlotMap::with_capacity(capacity),
+            ..Tree::empty()
+        }
+    }
+
+    /// Create a tree with no nodes that orders values with `cmp` and tests them for
+    /// equality with `eq`, rather than with `Ord` and `Eq`
+    ///
+    /// Inserting, `contains`, `delete` and the other searches for a single value
+    /// go left when `cmp` gives `Less` and right otherwise, stopping at a node `eq`
+    /// accepts, so `eq` can be looser or stricter than `cmp` being `Equal`
+    ///
+    /// # NOTE
+    ///
+    /// Range queries, ranks, `split`, `merge_join` and the other ordered operations on
+    /// a built tree use the comparator too. `T` still needs `Ord` for the bulk builders
+    /// such as `from_sorted` and `extend_sorted`, which keep using it, so they only
+    /// agree with the tree when `cmp` orders values the same way `Ord` does
+    pub fn new_with(cmp: fn(&T, &T) -> Ordering, eq: fn(&T, &T) -> bool) -> Self {
+        Self {
+            settings: TreeSettings {
+                comparator: Some(Comparator { cmp, eq }),
+                ..TreeSettings::default()
+            },
+            ..Tree::empty()
+        }
+    }
+
+    /// Create a tree with no nodes, sharing this tree's settings
+    fn empty_like(&self) -> Self {
+        Self {
+            settings: self.settings,
+            ..Tree::empty()
+        }
+    }
+
+    /// Choose what inserting does when an equal value is already in the tree
+    ///
+    /// The default is `DuplicatePolicy::Reject`
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.settings.duplicate_policy = policy;
+        self
+    }
+
+    /// Get what inserting does when an equal value is already in the tree
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.settings.duplicate_policy
+    }
+
+    /// Refuse inserts that would take the tree past `max_len` values
+    ///
+    /// Such inserts fail with `InsertError::Full`, while duplicates are still
+    /// handled by the duplicate policy as they do not add a node
+    ///
+    /// # NOTE
+    ///
+    /// Only inserts of single values are checked, so bulk operations such as
+    /// `extend_sorted` and `merge` can still take the tree past the limit
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.settings.max_len = Some(max_len);
+        self
+    }
+
+    /// Get the most values the tree will accept, if it was limited
+    pub fn max_len(&self) -> Option<usize> {
+        self.settings.max_len
+    }
+
+    /// Refuse inserts that would make the tree more than `max_height` nodes tall
+    ///
+    /// Such inserts fail with `InsertError::TooDeep`, so a sorted stream of untrusted
+    /// data cannot quietly turn the tree into a list. The caller can then `rebalance`
+    /// and try again
+    ///
+    /// # NOTE
+    ///
+    /// As with `with_max_len`, only inserts of single values are checked
+    pub fn with_max_height(mut self, max_height: usize) -> Self {
+        self.settings.max_height = Some(max_height);
+        self
+    }
+
+    /// Get the most levels the tree will grow to, if it was limited
+    pub fn max_height(&self) -> Option<usize> {
+        self.settings.max_height
+    }
+
+    /// Rebalance the whole tree whenever an insert makes it more than `factor` times
+    /// as tall as the fewest levels its values could fit in
+    ///
+    /// Inserts stay as cheap as before, with the occasional O(n) rebalance paying for
+    /// the many inserts that led up to it, so lookups stay O(log n) without keeping
+    /// the tree balanced after every change. `Tree::DEFAULT_REBALANCE_FACTOR` keeps
+    /// the height within about twice the best. A factor of zero or less turns this
+    /// off, which is the default
+    ///
+    /// # NOTE
+    ///
+    /// The nodes are relinked rather than rebuilt, so keys stay valid. A factor close
+    /// to one rebalances after nearly every insert
+    pub fn with_rebalance_factor(mut self, factor: f64) -> Self {
+        self.settings.rebalance_factor = (factor > 0.0).then_some(factor);
+        self
+    }
+
+    /// Get how much taller than the best the tree may grow before it is rebalanced, if set
+    pub fn rebalance_factor(&self) -> Option<f64> {
+        self.settings.rebalance_factor
+    }
+
+    /// Rebalance the tree if an insert left a node at `levels` deep, more than the
+    /// rebalance factor allows
+    fn rebalance_if_tall(&mut self, levels: usize) {
+        let Some(factor) = self.settings.rebalance_factor else {
+            return;
+        };
+
+        // The fewest levels that can hold this many nodes
+        let best_levels = (usize::BITS - self.len().leading_zeros()) as usize;
+        if levels as f64 > factor * best_levels as f64 {
+            self.rebalance();
+        }
+    }
+
+    /// Compact the storage whenever a removal leaves fewer than `ratio * capacity()` live nodes
+    ///
+    /// Compacting is a `defragment`, moving every node into fresh, densely packed
+    /// storage with the same shape and reclaiming the freed slots. A ratio of zero or less turns this off,
+    /// which is the default
+    ///
+    /// # WARNING
+    ///
+    /// Compacting gives every node a new key, so while this is on no key survives a
+    /// removal through `delete`, `pop_root`, `retain`, `take_if`, `retain_range`,
+    /// `remove_less_than`, `remove_greater_than` or `subtract_in_place`. Removals
+    /// through a `CursorMut` never compact, as that would move the node it is on
+    pub fn set_auto_compact(&mut self, ratio: f64) {
+        self.settings.auto_compact = (ratio > 0.0).then_some(ratio);
+    }
+
+    /// Compact the storage if auto compaction is on and enough of it is unused
+    fn compact_if_sparse(&mut self) {
+        let Some(ratio) = self.settings.auto_compact else {
+            return;
+        };
+
+        if (self.len() as f64) < ratio * self.capacity() as f64 {
+            self.defragment();
+        }
+    }
+
+    /// Move every node reachable from the root into fresh, densely packed storage,
+    /// keeping the exact shape
+    ///
+    /// The old storage is dropped along with its freed slots and any leaked nodes no
+    /// longer reachable from the root, so this reclaims the memory churn left behind.
+    /// Unlike `rebuild` nothing is rebalanced, so a deliberately chosen shape survives
+    ///
+    /// # NOTE
+    ///
+    /// Every node gets a new key, so no earlier key stays valid
+    pub fn defragment(&mut self) {
+        let mut storage = SlotMap::with_capacity(self.len());
+        self.root = self
+            .root
+            .map(|root| move_subtree(&mut self.storage, root, &mut storage));
+        self.storage = storage;
+        // The new storage counts its changes from zero, so it could match the cache
+        self.sorted_cache = None;
+    }
+
+    /// Check if a new node below this many others would go past the maximum height
+    fn too_deep(&self, depth: usize) -> bool {
+        self.settings
+            .max_height
+            .is_some_and(|max_height| depth >= max_height)
+    }
+
+    /// Check if adding another node would go past the maximum length
+    fn at_max_len(&self) -> bool {
+        self.settings
+            .max_len
+            .is_some_and(|max_len| self.len() >= max_len)
+    }
+
+    /// Apply the duplicate policy to data equal to the value in an existing node
+    fn insert_duplicate(&mut self, existing: SlotKey, data: T) -> Result<SlotKey, InsertError<T>> {
+        match self.settings.duplicate_policy {
+            // We cannot accept duplicates
+            DuplicatePolicy::Reject => Err(InsertError::Duplicate(data)),
+            DuplicatePolicy::Replace => Err(InsertError::Duplicate(core::mem::replace(
+                &mut self.storage.get_mut(existing).data,
+                data,
+            ))),
+            DuplicatePolicy::Ignore => Ok(existing),
+        }
+    }
+
+    /// Check if the tree has no nodes
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Remove every node, keeping the allocated storage for reuse
+    pub fn clear(&mut self) {
+        self.storage.clear();
+        self.root = None;
+    }
+
+    /// Remove every node and make `new_root` the only value, keeping the allocated
+    /// storage and the settings for reuse
+    ///
+    /// # NOTE
+    ///
+    /// The tree always holds exactly one value afterwards, so the maximum length and
+    /// height are not checked
+    pub fn reset(&mut self, new_root: T) {
+        self.clear();
+        self.root = Some(self.storage.insert(TreeNode::new(new_root)));
+    }
+
+    /// Remove the root node and return its data, or `None` if the tree is empty
+    ///
+    /// When the root has two children its in order successor is promoted to take
+    /// its place, as in a standard BST deletion
+    pub fn pop_root(&mut self) -> Option<T> {
+        let root = self.root?;
+        let data = self.remove_key(root);
+        self.compact_if_sparse();
+
+        Some(data)
+    }
+
+    /// Remove every value that `f` returns false for
+    ///
+    /// Values are removed in place one at a time, so the result is still a
+    /// valid BST and the keys of surviving nodes stay valid, unless auto
+    /// compaction then runs
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut rejected = Vec::new();
+        self.visit_keys(TreeOrdering::In, |node_key| {
+            if !f(&self.storage.get(node_key).data) {
+                rejected.push(node_key);
+            }
+        });
+
+        for node_key in rejected {
+            self.remove_key(node_key);
+        }
+        self.compact_if_sparse();
+    }
+
+    /// Rearrange the tree in place into a right leaning vine, where no node has a
+    /// left child and each right child is the in order successor
+    ///
+    /// Any node with a left child is rotated right until it has none, so this is
+    /// O(n) time with no extra space, and every key stays valid
+    pub fn flatten_to_vine(&mut self) {
+        let mut parent = None;
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            if self.storage.get(current_key).left.is_some() {
+                // Lift the left child into this place, and look at it next
+                let lifted = self.rotate(current_key, TreeDirection::Right);
+                self.link(parent, Some(lifted));
+                current = Some(lifted);
+            } else {
+                parent = Some((current_key, TreeDirection::Right));
+                current = self.storage.get(current_key).right;
+            }
+        }
+    }
+
+    /// Call `f` with the key of every node in the order provided
+    ///
+    /// This uses an explicit stack rather than recursion, so a degenerate
+    /// tree (e.g. built from sorted input) cannot overflow the call stack
+    fn visit_keys<F>(&self, ordering: TreeOrdering, mut f: F)
+    where
+        F: FnMut(SlotKey),
+    {
+        // Each entry is a node and whether its children have already been pushed
+        let mut stack: Vec<(SlotKey, bool)> =
+            self.root.map(|root| (root, false)).into_iter().collect();
+
+        while let Some((node_key, expanded)) = stack.pop() {
+            if expanded {
+                // Children have been handled, so this node is due
+                f(node_key);
+                continue;
+            }
+
+            let node = self.storage.get(node_key);
+
+            // Push in reverse of the order we want to visit, as the stack is LIFO
+            match ordering {
+                TreeOrdering::Pre => {
+                    if let Some(right) = node.right {
+                        stack.push((right, false));
+                    }
+                    if let Some(left) = node.left {
+                        stack.push((left, false));
+                    }
+                    stack.push((node_key, true));
+                }
+                TreeOrdering::In => {
+                    if let Some(right) = node.right {
+                        stack.push((right, false));
+                    }
+                    stack.push((node_key, true));
+                    if let Some(left) = node.left {
+                        stack.push((left, false));
+                    }
+                }
+                TreeOrdering::Post => {
+                    stack.push((node_key, true));
+                    if let Some(right) = node.right {
+                        stack.push((right, false));
+                    }
+                    if let Some(left) = node.left {
+                        stack.push((left, false));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Walk the keys in order lazily, holding only the path to the next node
+    fn in_order_keys(&self) -> impl Iterator<Item = SlotKey> + '_ {
+        let mut stack = Vec::new();
+        let mut current = self.root;
+
+        core::iter::from_fn(move || {
+            while let Some(current_key) = current {
+                stack.push(current_key);
+                current = self.storage.get(current_key).left;
+            }
+
+            let node_key = stack.pop()?;
+            current = self.storage.get(node_key).right;
+
+            Some(node_key)
+        })
+    }
+
+    /// Recalculate the size of a node from its children
+    fn update_size(&mut self, node_key: SlotKey) {
+        let node = self.storage.get(node_key);
+        let size =
+            1 + subtree_size(&self.storage, node.left) + subtree_size(&self.storage, node.right);
+
+        self.storage.get_mut(node_key).size = size;
+    }
+
+    /// Recalculate the sizes of a node and all of its ancestors, after its subtree changed
+    fn update_sizes_upward(&mut self, start: Option<SlotKey>) {
+        let mut current = start;
+
+        while let Some(current_key) = current {
+            self.update_size(current_key);
+            current = self.storage.get(current_key).parent;
+        }
+    }
+
+    /// Recalculate the size of every node, for when the tree was built without tracking them
+    fn update_all_sizes(&mut self) {
+        let mut order = Vec::with_capacity(self.storage.item_count);
+        self.visit_keys(TreeOrdering::Post, |node_key| order.push(node_key));
+
+        // Post order guarantees both children are done before their parent
+        for node_key in order {
+            self.update_size(node_key);
+        }
+    }
+
+    /// Rotate the subtree rooted at `key`, keeping the sizes of the two nodes that moved correct
+    ///
+    /// The caller must link the returned key into the place `key` used to be
+    fn rotate(&mut self, key: SlotKey, direction: TreeDirection) -> SlotKey {
+        let lifted = rotation::rotate(&mut self.storage, key, direction);
+
+        // The old root is now below the lifted node, so it has to be done first
+        self.update_size(key);
+        self.update_size(lifted);
+
+        lifted
+    }
+
+    /// Point a parent's child, or the root if there is no parent, at a node
+    fn link(&mut self, parent: Option<(SlotKey, TreeDirection)>, child: Option<SlotKey>) {
+        match parent {
+            Some((parent_key, direction)) => {
+                attach(&mut self.storage, parent_key, direction, child)
+            }
+            None => {
+                if let Some(child_key) = child {
+                    self.storage.get_mut(child_key).parent = None;
+                }
+
+                self.root = child;
+            }
+        }
+    }
+
+    /// Relink every node into the most balanced shape for its values
+    ///
+    /// Nodes keep their values and are only relinked, so this is O(n) and every key
+    /// stays valid. The shape is the one `from_sorted` builds
+    pub fn rebalance(&mut self) {
+        let order: Vec<SlotKey> = self.in_order_keys().collect();
+        self.relink_balanced(&order);
+    }
+
+    /// Relink these nodes, given in order, into the most balanced shape as the whole tree
+    ///
+    /// Nodes left out of `order` are not touched, so the caller must already have
+    /// removed them
+    fn relink_balanced(&mut self, order: &[SlotKey]) {
+        // Each entry is a range of nodes still to place, and where its root hangs
+        let mut stack = vec![(0, order.len(), None)];
+
+        while let Some((start, end, parent)) = stack.pop() {
+            if start == end {
+                // Clear whatever the parent used to have on this side
+                self.link(parent, None);
+                continue;
+            }
+
+            let middle = start + (end - start) / 2;
+            let node_key = order[middle];
+            self.storage.get_mut(node_key).size = end - start;
+            self.link(parent, Some(node_key));
+
+            stack.push((middle + 1, end, Some((node_key, TreeDirection::Right))));
+            stack.push((start, middle, Some((node_key, TreeDirection::Left))));
+        }
+    }
+
+    /// Find the parent of a node and which of its sides the node hangs off
+    ///
+    /// `None` means the node is the root
+    fn parent_link(&self, node_key: SlotKey) -> Option<(SlotKey, TreeDirection)> {
+        let parent_key = self.storage.get(node_key).parent?;
+
+        let direction = if self.storage.get(parent_key).left == Some(node_key) {
+            TreeDirection::Left
+        } else {
+            TreeDirection::Right
+        };
+
+        Some((parent_key, direction))
+    }
+
+    /// Remove a node from the tree in place, returning its data
+    ///
+    /// A node with two children is replaced by its in order successor, which is
+    /// relinked rather than copied so every other node keeps its key
+    pub(crate) fn remove_key(&mut self, node_key: SlotKey) -> T {
+        let parent = self.parent_link(node_key);
+        let node = self.storage.get(node_key);
+
+        // The deepest node whose subtree changed, where the size fixes start from
+        let changed_from = match (node.left, node.right) {
+            (Some(left), Some(right)) => {
+                let mut successor = right;
+                while let Some(smaller) = self.storage.get(successor).left {
+                    successor = smaller;
+                }
+
+                let changed_from = if successor == right {
+                    successor
+                } else {
+                    // Take the successor out of its place, leaving its right child behind
+                    let successor_parent = self
+                        .storage
+                        .get(successor)
+                        .parent
+                        .expect("The successor is below the node, so it has a parent");
+                    let successor_right = self.storage.get(successor).right;
+                    attach(
+                        &mut self.storage,
+                        successor_parent,
+                        TreeDirection::Left,
+                        successor_right,
+                    );
+                    attach(
+                        &mut self.storage,
+                        successor,
+                        TreeDirection::Right,
+                        Some(right),
+                    );
+
+                    successor_parent
+                };
+
+                attach(
+                    &mut self.storage,
+                    successor,
+                    TreeDirection::Left,
+                    Some(left),
+                );
+                self.link(parent, Some(successor));
+
+                Some(changed_from)
+            }
+            (child, None) | (None, child) => {
+                self.link(parent, child);
+
+                parent.map(|(parent_key, _)| parent_key)
+            }
+        };
+
+        self.update_sizes_upward(changed_from);
+
+        self.storage.remove(node_key).data
+    }
+
+    /// Free every node of a subtree that has already been unlinked from the tree
+    fn free_subtree(&mut self, subtree_root: Option<SlotKey>) {
+        let mut stack: Vec<SlotKey> = subtree_root.into_iter().collect();
+
+        while let Some(node_key) = stack.pop() {
+            let node = self.storage.remove(node_key);
+
+            stack.extend(node.left);
+            stack.extend(node.right);
+        }
+    }
+
+    /// Count the values in the tree
+    pub fn len(&self) -> usize {
+        self.storage.item_count
+    }
+
+    /// Get the number of node slots allocated, including ones freed by earlier removals
+    pub fn capacity(&self) -> usize {
+        self.storage.capacity()
+    }
+
+    /// Collect the keys of every node in the order provided
+    ///
+    /// A key stays valid until its node is removed, after which its slot may be
+    /// reused by a later insert
+    pub fn iter_keys(&self, ordering: TreeOrdering) -> impl Iterator<Item = SlotKey> {
+        let mut keys = Vec::with_capacity(self.storage.item_count);
+        self.visit_keys(ordering, |node_key| keys.push(node_key));
+
+        keys.into_iter()
+    }
+
+    /// Iterate over the values in order, without collecting them first
+    pub fn iter_in_order(&self) -> InOrderIter<'_, T> {
+        let mut iter = InOrderIter {
+            storage: &self.storage,
+            stack: Vec::new(),
+        };
+        iter.push_left_spine(self.root);
+
+        iter
+    }
+
+    /// Collect references to the values in order, the same as `to_vec(TreeOrdering::In)`
+    pub fn flatten_sorted(&self) -> Vec<&T> {
+        self.iter_in_order().collect()
+    }
+
+    /// Get the keys of every node in order, working them out only if the tree changed
+    ///
+    /// The first call walks the tree and keeps the keys, and later calls hand back the
+    /// same slice until the tree is changed. On a tree that is mostly read this turns
+    /// repeated searches into binary searches over a flat slice, which is kinder to the
+    /// cache than following links
+    ///
+    /// # NOTE
+    ///
+    /// Anything that could change a node, even through `&mut` access to a value that
+    /// leaves the order alone, makes the next call walk the tree again. The slice
+    /// borrows the tree, so a stale one can never be read
+    pub fn sorted_cache(&mut self) -> &[SlotKey] {
+        let fresh = self
+            .sorted_cache
+            .as_ref()
+            .is_some_and(|cache| cache.changes == self.storage.changes && cache.root == self.root);
+
+        if !fresh {
+            let keys = self.in_order_keys().collect();
+            self.sorted_cache = Some(SortedCache {
+                changes: self.storage.changes,
+                root: self.root,
+                keys,
+            });
+        }
+
+        &self
+            .sorted_cache
+            .as_ref()
+            .expect("The cache was just filled")
+            .keys
+    }
+
+    /// Iterate over the values in order, grouped into Vecs of `n` with a shorter last one
+    ///
+    /// # Panics
+    ///
+    /// If `n` is zero
+    pub fn chunks_in_order(&self, n: usize) -> impl Iterator<Item = Vec<&T>> {
+        assert!(n > 0, "Chunks must hold at least one value");
+        let mut values = self.iter_in_order();
+
+        core::iter::from_fn(move || {
+            let chunk: Vec<&T> = values.by_ref().take(n).collect();
+
+            (!chunk.is_empty()).then_some(chunk)
+        })
+    }
+
+    /// Iterate over the values in order, each with its position counting from zero
+    ///
+    /// The position is the value's rank, so it stays the same for as long as the
+    /// tree is not changed, unlike a `SlotKey` which reflects when a node was inserted
+    pub fn enumerate_in_order(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.iter_in_order().enumerate()
+    }
+
+    /// Iterate over the values in the order of their slots, which is the order they
+    /// were inserted as long as nothing has been removed
+    ///
+    /// A removal frees a slot for the next insert to reuse, after which that value
+    /// comes out in the freed slot's place rather than last
+    pub fn insertion_order_iter(&self) -> impl Iterator<Item = &T> {
+        self.storage.values().map(|node| &node.data)
+    }
+
+    /// Iterate over the keys and values together in order, without collecting them first
+    pub fn iter_with_keys(&self) -> impl Iterator<Item = (SlotKey, &T)> {
+        let mut iter = self.iter_in_order();
+
+        core::iter::from_fn(move || iter.next_entry())
+    }
+
+    /// Iterate over the values in pre order, without collecting them first
+    pub fn iter_pre_order(&self) -> PreOrderIter<'_, T> {
+        PreOrderIter {
+            storage: &self.storage,
+            stack: self.root.into_iter().collect(),
+        }
+    }
+
+    /// Iterate over the values in post order, without collecting them first
+    pub fn iter_post_order(&self) -> PostOrderIter<'_, T> {
+        let mut iter = PostOrderIter {
+            storage: &self.storage,
+            stack: Vec::new(),
+        };
+        iter.push_first_leaf(self.root);
+
+        iter
+    }
+
+    /// Walk the tree in pre order, letting `f` cut off the subtrees of each node it sees
+    ///
+    /// A subtree skipped with `WalkControl::SkipChildren` is never entered, so a
+    /// search that can rule out whole branches only visits the nodes it has to
+    pub fn walk_pruned<F>(&self, mut f: F)
+    where
+        F: FnMut(&T) -> WalkControl,
+    {
+        let mut stack: Vec<SlotKey> = self.root.into_iter().collect();
+
+        while let Some(current_key) = stack.pop() {
+            let current_node = self.storage.get(current_key);
+
+            match f(&current_node.data) {
+                WalkControl::Continue => {
+                    // Right is pushed first so the left side is visited first
+                    stack.extend(current_node.right);
+                    stack.extend(current_node.left);
+                }
+                WalkControl::SkipChildren => {}
+                WalkControl::Stop => return,
+            }
+        }
+    }
+
+    /// Feed the shape and values of the tree into a hasher
+    ///
+    /// Nodes are hashed in pre order with a marker for every missing child, so two
+    /// trees hash the same only if they hold the same values in the same shape.
+    /// Trees holding the same values in different shapes will almost always differ
+    pub fn structural_hash<H>(&self, h: &mut H)
+    where
+        T: Hash,
+        H: Hasher,
+    {
+        let mut stack = vec![self.root];
+
+        while let Some(current) = stack.pop() {
+            let Some(current_key) = current else {
+                h.write_u8(0);
+                continue;
+            };
+            let current_node = self.storage.get(current_key);
+
+            h.write_u8(1);
+            current_node.data.hash(h);
+
+            // Right is pushed first so the left side is hashed first
+            stack.push(current_node.right);
+            stack.push(current_node.left);
+        }
+    }
+
+    /// Encode the shape of the tree alone, ignoring the values
+    ///
+    /// Positions are walked in pre order, writing a 1 bit for a node and a 0 bit for a
+    /// missing child, packed into bytes from the most significant bit down. The walk
+    /// ends itself, so the zero bits padding out the last byte cannot be mistaken for
+    /// nodes, and two trees give the same signature exactly when they have the same shape
+    pub fn shape_signature(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity((2 * self.len() + 1).div_ceil(8));
+        let mut bit_count = 0usize;
+        let mut stack = vec![self.root];
+
+        while let Some(current) = stack.pop() {
+            if bit_count.is_multiple_of(8) {
+                out.push(0);
+            }
+
+            if let Some(current_key) = current {
+                let current_node = self.storage.get(current_key);
+
+                *out.last_mut().expect("A byte was pushed for this bit") |= 0x80 >> (bit_count % 8);
+
+                // Right is pushed first so the left side is written first
+                stack.push(current_node.right);
+                stack.push(current_node.left);
+            }
+
+            bit_count += 1;
+        }
+
+        out
+    }
+
+    /// Check if `other` is this tree flipped left to right, holding equal values
+    ///
+    /// Nodes are compared in pairs from a queue, so this does not recurse and stops at
+    /// the first difference. Two empty trees are mirrors
+    pub fn is_mirror(&self, other: &Tree<T>) -> bool
+    where
+        T: PartialEq,
+    {
+        self.mirrors(other, self.root, other.root)
+    }
+
+    /// Check if the tree is its own mirror image, with equal values in mirrored places
+    ///
+    /// In a BST this only holds for trees of at most one value, as mirrored values
+    /// on the two sides cannot be equal
+    pub fn is_symmetric(&self) -> bool
+    where
+        T: PartialEq,
+    {
+        let Some(root) = self.root else {
+            return true;
+        };
+        let root_node = self.storage.get(root);
+
+        self.mirrors(self, root_node.left, root_node.right)
+    }
+
+    /// Check if the subtree at `left` in this tree mirrors the one at `right` in `other`
+    fn mirrors(&self, other: &Tree<T>, left: Option<SlotKey>, right: Option<SlotKey>) -> bool
+    where
+        T: PartialEq,
+    {
+        let mut queue = VecDeque::from([(left, right)]);
+
+        while let Some(pair) = queue.pop_front() {
+            let (left_key, right_key) = match pair {
+                (None, None) => continue,
+                (Some(left_key), Some(right_key)) => (left_key, right_key),
+                _ => return false,
+            };
+            let left_node = self.storage.get(left_key);
+            let right_node = other.storage.get(right_key);
+
+            if left_node.data != right_node.data {
+                return false;
+            }
+
+            // Outer children pair up, as do inner ones
+            queue.push_back((left_node.left, right_node.right));
+            queue.push_back((left_node.right, right_node.left));
+        }
+
+        true
+    }
+
+    /// Get the node a key points at, or `None` if that slot is empty
+    ///
+    /// The node's accessors allow walking the tree by hand
+    pub fn node(&self, key: SlotKey) -> Option<&TreeNode<T>> {
+        self.storage.try_get(key)
+    }
+
+    /// Get the data of the node a key points at, or `None` if that slot is empty
+    pub fn get_by_key(&self, key: SlotKey) -> Option<&T> {
+        self.node(key).map(|node| &node.data)
+    }
+
+    /// Count the nodes in the subtree rooted at a key, including that node
+    ///
+    /// Every node keeps the size of its subtree, so this is O(1). A key whose slot is
+    /// empty gives 0
+    pub fn subtree_size(&self, key: SlotKey) -> usize {
+        self.node(key).map_or(0, |node| node.size)
+    }
+
+    /// Check if a key points at a node that is part of the tree
+    ///
+    /// The slot must be live and its parent links must lead up to the root through
+    /// nodes that have it as a child, so a leaked slot no longer reachable from the
+    /// root gives `false`. This walks up
+    /// the tree, so it is O(height)
+    pub fn contains_key(&self, key: SlotKey) -> bool {
+        let mut current_key = key;
+
+        // Any chain longer than the number of nodes has gone round a cycle
+        for _ in 0..self.storage.item_count {
+            let Some(current_node) = self.storage.try_get(current_key) else {
+                return false;
+            };
+
+            let Some(parent_key) = current_node.parent else {
+                return Some(current_key) == self.root;
+            };
+
+            // A leaked node can still point at a parent that has since moved on
+            let is_child = self.storage.try_get(parent_key).is_some_and(|parent| {
+                parent.left == Some(current_key) || parent.right == Some(current_key)
+            });
+            if !is_child {
+                return false;
+            }
+
+            current_key = parent_key;
+        }
+
+        false
+    }
+
+    /// Get mutable access to the data of the node a key points at, or `None` if that slot is empty
+    ///
+    /// # WARNING
+    ///
+    /// The new value must order the same way relative to the others, as the
+    /// tree is not rearranged afterwards
+    pub fn get_mut_by_key(&mut self, key: SlotKey) -> Option<&mut T> {
+        self.storage.try_get_mut(key).map(|node| &mut node.data)
+    }
+
+    /// Overwrite `dst` with a copy of this tree, reusing the space `dst` already has
+    ///
+    /// The copy has the same shape, settings and keys, and any sorted cache `dst`
+    /// had is dropped
+    pub fn clone_into(&self, dst: &mut Tree<T>)
+    where
+        T: Clone,
+    {
+        self.storage.map_into(&mut dst.storage, |node| TreeNode {
+            data: node.data.clone(),
+            left: node.left,
+            right: node.right,
+            parent: node.parent,
+            size: node.size,
+        });
+        dst.root = self.root;
+        dst.settings = self.settings;
+        dst.sorted_cache = None;
+    }
+
+    /// Check if following child links from the root ever reaches the same node twice
+    ///
+    /// Every traversal would loop forever on such a tree. Visited nodes are tracked
+    /// in a bitset indexed by slot, and a link to an empty slot also counts as corrupt
+    pub fn has_cycle(&self) -> bool {
+        let bit_length = u64::BITS as usize;
+        let mut visited = vec![0_u64; self.storage.capacity().div_ceil(bit_length)];
+
+        let mut stack: Vec<SlotKey> = self.root.into_iter().collect();
+
+        while let Some(node_key) = stack.pop() {
+            let Some(node) = self.node(node_key) else {
+                return true;
+            };
+
+            let (chunk, bit) = (node_key.index / bit_length, node_key.index % bit_length);
+            if visited[chunk] & (1 << bit) != 0 {
+                return true;
+            }
+            visited[chunk] |= 1 << bit;
+
+            stack.extend(node.left);
+            stack.extend(node.right);
+        }
+
+        false
+    }
+
+    /// Find every occupied slot that following child links from the root never reaches
+    ///
+    /// These are nodes leaked by a removal that unlinked them without freeing their
+    /// slot, so an empty Vec means the storage is clean. Keys come in slot order, and
+    /// broken links or cycles are stepped over rather than followed forever
+    pub fn orphaned_slots(&self) -> Vec<SlotKey> {
+        let bit_length = u64::BITS as usize;
+        let mut visited = vec![0_u64; self.storage.capacity().div_ceil(bit_length)];
+
+        let mut stack: Vec<SlotKey> = self.root.into_iter().collect();
+
+        while let Some(node_key) = stack.pop() {
+            let Some(node) = self.node(node_key) else {
+                continue;
+            };
+
+            let (chunk, bit) = (node_key.index / bit_length, node_key.index % bit_length);
+            if visited[chunk] & (1 << bit) != 0 {
+                continue;
+            }
+            visited[chunk] |= 1 << bit;
+
+            stack.extend(node.left);
+            stack.extend(node.right);
+        }
+
+        self.storage
+            .iter()
+            .map(|(node_key, _)| node_key)
+            .filter(|node_key| {
+                let (chunk, bit) = (node_key.index / bit_length, node_key.index % bit_length);
+                visited[chunk] & (1 << bit) == 0
+            })
+            .collect()
+    }
+
+    /// Take the tree apart into its node storage and the key of its root
+    ///
+    /// The tree's settings, such as its duplicate policy, are dropped
+    pub fn into_parts(self) -> (SlotMap<TreeNode<T>>, Option<SlotKey>) {
+        (self.storage, self.root)
+    }
+
+    /// Put a tree back together from node storage and the key of its root
+    ///
+    /// Subtree sizes are recalculated, but the child and parent links are trusted
+    /// and every node should be reachable from the root. In debug builds this checks
+    /// the root is a live node and the links have no cycles
+    ///
+    /// # WARNING
+    ///
+    /// Nothing checks the values are in BST order. Out of order values cannot cause
+    /// undefined behaviour, but searches will then give wrong results
+    pub fn from_parts(storage: SlotMap<TreeNode<T>>, root: Option<SlotKey>) -> Self {
+        let mut tree = Tree {
+            storage,
+            root,
+            ..Tree::empty()
+        };
+
+        debug_assert!(
+            root.is_none_or(|root| tree.node(root).is_some()),
+            "The root must be a live node"
+        );
+        debug_assert!(!tree.has_cycle(), "The child links must not have cycles");
+
+        tree.update_all_sizes();
+
+        tree
+    }
+
+    /// Build a tree from a list of nodes, each a value with the positions of its
+    /// left and right children in the list, and the position of the root
+    ///
+    /// This is meant for reloading a dump by hand. The links are checked to be in
+    /// range, every node but the root must be the child of exactly one other, and
+    /// every node must be reachable from the root. An empty list gives an empty tree
+    ///
+    /// # NOTE
+    ///
+    /// The values are not checked to be in BST order, so a broken tree can be
+    /// reproduced as it was. Use `check_order` for that
+    pub fn try_from_nodes(
+        nodes: Vec<(T, Option<usize>, Option<usize>)>,
+        root: usize,
+    ) -> Result<Tree<T>, String> {
+        if nodes.is_empty() {
+            return Ok(Tree::empty());
+        }
+        if root >= nodes.len() {
+            return Err(format!(
+                "Root {} is out of range for {} nodes",
+                root,
+                nodes.len()
+            ));
+        }
+
+        let mut parents = vec![None; nodes.len()];
+        for (index, (_, left, right)) in nodes.iter().enumerate() {
+            for child in [left, right].into_iter().flatten().copied() {
+                if child >= nodes.len() {
+                    return Err(format!(
+                        "Node {} has child {} which is out of range",
+                        index, child
+                    ));
+                }
+                if child == root {
+                    return Err(format!("Node {} has the root {} as a child", index, root));
+                }
+                if let Some(parent) = parents[child] {
+                    return Err(format!(
+                        "Node {} is a child of both {} and {}",
+                        child, parent, index
+                    ));
+                }
+
+                parents[child] = Some(index);
+            }
+        }
+
+        if let Some(orphan) =
+            (0..nodes.len()).find(|&index| index != root && parents[index].is_none())
+        {
+            return Err(format!("Node {} is not the child of any node", orphan));
+        }
+
+        // Every node now has one parent, so any node not reached from the root is on a cycle
+        let mut reached = 0;
+        let mut stack = vec![root];
+        while let Some(index) = stack.pop() {
+            let (_, left, right) = &nodes[index];
+            reached += 1;
+
+            stack.extend(*left);
+            stack.extend(*right);
+        }
+        if reached != nodes.len() {
+            return Err(format!(
+                "Only {} of {} nodes are reachable from the root, the rest form a cycle",
+                reached,
+                nodes.len()
+            ));
+        }
+
+        let mut storage = SlotMap::new();
+        let links: Vec<_> = nodes
+            .into_iter()
+            .map(|(data, left, right)| (storage.insert(TreeNode::new(data)), left, right))
+            .collect();
+
+        for (index, &(node_key, left, right)) in links.iter().enumerate() {
+            let node = storage.get_mut(node_key);
+            node.left = left.map(|child| links[child].0);
+            node.right = right.map(|child| links[child].0);
+            node.parent = parents[index].map(|parent| links[parent].0);
+        }
+
+        Ok(Tree::from_parts(storage, Some(links[root].0)))
+    }
+
+    /// Get the key of the root node, or `None` if the tree is empty
+    pub fn root_key(&self) -> Option<SlotKey> {
+        self.root
+    }
+
+    /// Get the key of the node holding the smallest value, or `None` if the tree is empty
+    pub fn min_key(&self) -> Option<SlotKey> {
+        self.end_key(TreeDirection::Left)
+    }
+
+    /// Get the key of the node holding the largest value, or `None` if the tree is empty
+    pub fn max_key(&self) -> Option<SlotKey> {
+        self.end_key(TreeDirection::Right)
+    }
+
+    /// Follow one side down from the root as far as it goes
+    fn end_key(&self, direction: TreeDirection) -> Option<SlotKey> {
+        let mut current_key = self.root?;
+
+        while let Some(next_key) = self.storage.get(current_key).child(direction) {
+            current_key = next_key;
+        }
+
+        Some(current_key)
+    }
+
+    /// Get the values from the root down the left side, ending at the smallest
+    pub fn left_spine(&self) -> Vec<&T> {
+        self.spine(TreeDirection::Left)
+    }
+
+    /// Get the values from the root down the right side, ending at the largest
+    ///
+    /// A tree built by inserting sorted values is all right spine, which shows how
+    /// far it is from balanced
+    pub fn right_spine(&self) -> Vec<&T> {
+        self.spine(TreeDirection::Right)
+    }
+
+    /// Collect the values from the root down one side as far as it goes
+    fn spine(&self, direction: TreeDirection) -> Vec<&T> {
+        let mut out = Vec::new();
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            let current_node = self.storage.get(current_key);
+            out.push(&current_node.data);
+            current = current_node.child(direction);
+        }
+
+        out
+    }
+
+    /// Pick a value uniformly at random, or `None` if the tree is empty
+    ///
+    /// A position is drawn from `0..len()` and found by descending with the subtree
+    /// sizes, so every value is equally likely whatever the shape, in O(height)
+    #[cfg(feature = "rand")]
+    pub fn sample<R>(&self, rng: &mut R) -> Option<&T>
+    where
+        R: rand::Rng + ?Sized,
+    {
+        if self.is_empty() {
+            return None;
+        }
+
+        let index = rng.random_range(0..self.len());
+
+        self.nth_key(index)
+            .map(|node_key| &self.storage.get(node_key).data)
+    }
+
+    /// Find the node at this position in order, descending with the subtree sizes
+    fn nth_key(&self, mut index: usize) -> Option<SlotKey> {
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            let current_node = self.storage.get(current_key);
+            let left_size = subtree_size(&self.storage, current_node.left);
+
+            current = match index.cmp(&left_size) {
+                Ordering::Equal => return Some(current_key),
+                Ordering::Less => current_node.left,
+                Ordering::Greater => {
+                    index -= left_size + 1;
+                    current_node.right
+                }
+            };
+        }
+
+        None
+    }
+
+    /// Get a cursor starting at the smallest value
+    pub fn cursor_min(&self) -> Cursor<'_, T> {
+        Cursor {
+            tree: self,
+            current: self.min_key(),
+        }
+    }
+
+    /// Get a cursor starting at the largest value
+    pub fn cursor_max(&self) -> Cursor<'_, T> {
+        Cursor {
+            tree: self,
+            current: self.max_key(),
+        }
+    }
+
+    /// Get a cursor starting at the smallest value that can remove values as it goes
+    pub fn cursor_min_mut(&mut self) -> CursorMut<'_, T> {
+        CursorMut {
+            current: self.min_key(),
+            tree: self,
+        }
+    }
+
+    /// Get a cursor starting at the largest value that can remove values as it goes
+    pub fn cursor_max_mut(&mut self) -> CursorMut<'_, T> {
+        CursorMut {
+            current: self.max_key(),
+            tree: self,
+        }
+    }
+
+    /// Get the key of the node after this one in order, or `None` at the largest value
+    ///
+    /// Parent links are followed up where there is no right subtree, so walking every
+    /// key this way is O(n) overall. A key whose slot is empty also gives `None`
+    pub fn successor_key(&self, key: SlotKey) -> Option<SlotKey> {
+        self.node(key)?;
+        self.next_key(key)
+    }
+
+    /// Get the key of the node before this one in order, or `None` at the smallest value
+    ///
+    /// This mirrors `successor_key`
+    pub fn predecessor_key(&self, key: SlotKey) -> Option<SlotKey> {
+        self.node(key)?;
+        self.prev_key(key)
+    }
+
+    /// Find the node after this one in order, by following parent links up where needed
+    fn next_key(&self, node_key: SlotKey) -> Option<SlotKey> {
+        self.step_key(node_key, TreeDirection::Right)
+    }
+
+    /// Find the node before this one in order, by following parent links up where needed
+    fn prev_key(&self, node_key: SlotKey) -> Option<SlotKey> {
+        self.step_key(node_key, TreeDirection::Left)
+    }
+
+    /// Step one place in order, towards larger values for `Right` and smaller for `Left`
+    fn step_key(&self, node_key: SlotKey, direction: TreeDirection) -> Option<SlotKey> {
+        let node = self.storage.get(node_key);
+
+        if let Some(mut current_key) = node.child(direction) {
+            // The next node is the nearest one in that subtree
+            while let Some(next_key) = self.storage.get(current_key).child(direction.opposite()) {
+                current_key = next_key;
+            }
+
+            return Some(current_key);
+        }
+
+        // Otherwise climb until we arrive from the other side of an ancestor
+        let mut current_key = node_key;
+        loop {
+            let parent_key = self.storage.get(current_key).parent?;
+
+            if self.storage.get(parent_key).child(direction) != Some(current_key) {
+                return Some(parent_key);
+            }
+
+            current_key = parent_key;
+        }
+    }
+
+    /// Get the key of a node's left child
+    pub fn left_child(&self, key: SlotKey) -> Option<SlotKey> {
+        self.node(key)?.left
+    }
+
+    /// Get the key of a node's right child
+    pub fn right_child(&self, key: SlotKey) -> Option<SlotKey> {
+        self.node(key)?.right
+    }
+
+    /// Get the key of a node's parent, which is `None` for the root
+    pub fn parent(&self, key: SlotKey) -> Option<SlotKey> {
+        self.node(key)?.parent
+    }
+
+    /// Get the key of the other child of a node's parent
+    pub fn sibling(&self, key: SlotKey) -> Option<SlotKey> {
+        let parent = self.storage.get(self.parent(key)?);
+
+        if parent.left == Some(key) {
+            parent.right
+        } else {
+            parent.left
+        }
+    }
+
+    /// Rebuild a tree from a breadth first listing, where `None` marks a missing child
+    ///
+    /// This is the inverse of `to_level_order` and preserves the exact shape given,
+    /// so the result is only a valid BST if the input describes one.
+    /// A listing that is empty or starts with `None` gives an empty tree
+    pub fn from_level_order(data: Vec<Option<T>>) -> Self {
+        let mut items = data.into_iter();
+
+        let Some(root) = items.next().flatten() else {
+            return Tree::empty();
+        };
+
+        let mut tree = Tree::new(root);
+
+        // Parents waiting to have their children read, in breadth first order
+        let mut queue = VecDeque::<SlotKey>::new();
+        queue.extend(tree.root);
+
+        'read: while let Some(parent_key) = queue.pop_front() {
+            // Each parent consumes the next two entries as its left then right child
+            for direction in [TreeDirection::Left, TreeDirection::Right] {
+                let Some(entry) = items.next() else {
+                    // Anything not listed is missing
+                    break 'read;
+                };
+
+                let Some(data) = entry else {
+                    continue;
+                };
+
+                let child_key = tree.storage.insert(TreeNode::new(data));
+                attach(&mut tree.storage, parent_key, direction, Some(child_key));
+
+                queue.push_back(child_key);
+            }
+        }
+
+        tree.update_all_sizes();
+        tree
+    }
+
+    /// Read a tree from a breadth first listing such as `[1,2,3,null,null,4,5]`
+    ///
+    /// This is the text form of `from_level_order`, so `null` marks a missing child and
+    /// missing nodes have no entries for their children. The brackets may be left out,
+    /// and whitespace around entries is ignored. An empty listing gives an empty tree
+    pub fn from_bracket_str(s: &str) -> Result<Self, ParseError>
+    where
+        T: core::str::FromStr,
+    {
+        let s = s.trim();
+        let inner = match (s.strip_prefix('['), s.ends_with(']')) {
+            (Some(rest), true) => rest.strip_suffix(']').unwrap_or_default(),
+            (None, false) => s,
+            _ => return Err(ParseError::UnmatchedBracket),
+        };
+        if inner.trim().is_empty() {
+            return Ok(Tree::empty());
+        }
+
+        let mut entries = Vec::new();
+        // Nodes read so far, each of which takes the next two entries as its children
+        let mut present = 0;
+
+        for (index, token) in inner.split(',').map(str::trim).enumerate() {
+            let entry = match token {
+                "null" => None,
+                _ => Some(token.parse().map_err(|_| ParseError::BadToken {
+                    index,
+                    token: token.into(),
+                })?),
+            };
+
+            // Entry i is a child of the (i - 1) / 2th node, which must have been read
+            if entry.is_some() {
+                if index > 0 && (index - 1) / 2 >= present {
+                    return Err(ParseError::Orphan(index));
+                }
+                present += 1;
+            }
+
+            entries.push(entry);
+        }
+
+        Ok(Tree::from_level_order(entries))
+    }
+
+    /// Rebuild any binary tree from its preorder and inorder listings
+    ///
+    /// The first preorder value is the root, and where it sits in the inorder listing
+    /// splits the rest into the left and right subtrees. This is done with a stack rather
+    /// than recursion, so is O(n) and safe on deep trees. Values must be unique for the
+    /// shape to be unambiguous, and `None` is given if the listings do not describe the
+    /// same tree
+    pub fn from_traversals(preorder: Vec<T>, inorder: Vec<T>) -> Option<Self>
+    where
+        T: Eq,
+    {
+        if preorder.len() != inorder.len() {
+            return None;
+        }
+
+        let mut items = preorder.into_iter();
+        let Some(root) = items.next() else {
+            return Some(Tree::empty());
+        };
+
+        let mut tree = Tree::new(root);
+
+        // Nodes whose right child has not been found yet, the top being the deepest
+        let mut stack: Vec<SlotKey> = tree.root.into_iter().collect();
+        let mut inorder_index = 0;
+
+        for data in items {
+            let new_node = tree.storage.insert(TreeNode::new(data));
+
+            let top = *stack
+                .last()
+                .expect("Stack always holds the last node added");
+
+            if inorder.get(inorder_index) != Some(&tree.storage.get(top).data) {
+                // The top's left subtree is not finished yet, so this starts or continues it
+                attach(&mut tree.storage, top, TreeDirection::Left, Some(new_node));
+            } else {
+                // Climb past every node whose inorder position has been reached,
+                // the last one passed is where the right subtree starts
+                let mut parent = top;
+                while let Some(&top) = stack.last() {
+                    if inorder.get(inorder_index) != Some(&tree.storage.get(top).data) {
+                        break;
+                    }
+
+                    parent = top;
+                    stack.pop();
+                    inorder_index += 1;
+                }
+
+                attach(
+                    &mut tree.storage,
+                    parent,
+                    TreeDirection::Right,
+                    Some(new_node),
+                );
+            }
+
+            stack.push(new_node);
+        }
+
+        // Building always reproduces the preorder, so checking the inorder is enough
+        let mut consistent = true;
+        let mut expected = inorder.iter();
+        tree.visit_keys(TreeOrdering::In, |node_key| {
+            if expected.next() != Some(&tree.storage.get(node_key).data) {
+                consistent = false;
+            }
+        });
+
+        if !consistent {
+            return None;
+        }
+
+        tree.update_all_sizes();
+        Some(tree)
+    }
+
+    /// List the tree breadth first, with `None` in place of each missing child
+    ///
+    /// Trailing `None`s are trimmed, so a lone root becomes `[Some(root)]`
+    pub fn to_level_order(&self) -> Vec<Option<&T>> {
+        let mut out = Vec::new();
+
+        let mut queue = VecDeque::<Option<SlotKey>>::new();
+        queue.push_back(self.root);
+
+        while let Some(entry) = queue.pop_front() {
+            let Some(node_key) = entry else {
+                out.push(None);
+                continue;
+            };
+
+            let node = self.storage.get(node_key);
+            out.push(Some(&node.data));
+
+            // Missing children are still queued so they take up their position
+            queue.push_back(node.left);
+            queue.push_back(node.right);
+        }
+
+        // Remove the placeholders for the children of the last level
+        while let Some(None) = out.last() {
+            out.pop();
+        }
+
+        out
+    }
+}
+
+impl<T> Debug for Tree<T>
+where
+    T: Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Tree")
+            .field("storage", &self.storage)
+            .field("root", &self.root)
+            .field("settings", &self.settings)
+            .finish()
+    }
+}
+
+/// Prints the values in order as a set, e.g. `{A, C, D}`
+impl<T> Display for Tree<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut result = f.write_str("{");
+        let mut first = true;
+
+        self.visit_keys(TreeOrdering::In, |node_key| {
+            if result.is_err() {
+                return;
+            }
+
+            let separator = if first { "" } else { ", " };
+            first = false;
+
+            result = write!(f, "{}{}", separator, self.storage.get(node_key).data);
+        });
+
+        result?;
+        f.write_str("}")
+    }
+}
+
+/// An in order iterator over the values of a tree, from `Tree::iter_in_order`
+///
+/// The stack holds the path to the next node, so it uses O(height) memory
+pub struct InOrderIter<'a, T> {
+    storage: &'a SlotMap<TreeNode<T>>,
+    stack: Vec<SlotKey>,
+}
+
+impl<'a, T> InOrderIter<'a, T> {
+    /// Push a node and every node down its left side
+    fn push_left_spine(&mut self, mut current: Option<SlotKey>) {
+        while let Some(current_key) = current {
+            self.stack.push(current_key);
+            current = self.storage.get(current_key).left;
+        }
+    }
+
+    /// Step to the next node, giving its key along with its value
+    fn next_entry(&mut self) -> Option<(SlotKey, &'a T)> {
+        let node_key = self.stack.pop()?;
+        let node = self.storage.get(node_key);
+        self.push_left_spine(node.right);
+
+        Some((node_key, &node.data))
+    }
+}
+
+impl<'a, T> Iterator for InOrderIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.next_entry().map(|(_, data)| data)
+    }
+}
+
+/// A pre order iterator over the values of a tree, from `Tree::iter_pre_order`
+///
+/// The stack holds the right children still to be visited
+pub struct PreOrderIter<'a, T> {
+    storage: &'a SlotMap<TreeNode<T>>,
+    stack: Vec<SlotKey>,
+}
+
+impl<'a, T> Iterator for PreOrderIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let node = self.storage.get(self.stack.pop()?);
+
+        // Right is pushed first so the left side is visited first
+        self.stack.extend(node.right);
+        self.stack.extend(node.left);
+
+        Some(&node.data)
+    }
+}
+
+/// A post order iterator over the values of a tree, from `Tree::iter_post_order`
+///
+/// The stack holds the path to the next node, so it uses O(height) memory
+pub struct PostOrderIter<'a, T> {
+    storage: &'a SlotMap<TreeNode<T>>,
+    stack: Vec<SlotKey>,
+}
+
+impl<'a, T> PostOrderIter<'a, T> {
+    /// Push the path from a node down to the first leaf reached by preferring left children
+    fn push_first_leaf(&mut self, mut current: Option<SlotKey>) {
+        while let Some(current_key) = current {
+            self.stack.push(current_key);
+
+            let node = self.storage.get(current_key);
+            current = node.left.or(node.right);
+        }
+    }
+}
+
+impl<'a, T> Iterator for PostOrderIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let node_key = self.stack.pop()?;
+
+        // Coming up from a left child means the right side still has to be done
+        if let Some(&parent_key) = self.stack.last() {
+            let parent = self.storage.get(parent_key);
+
+            if parent.left == Some(node_key) {
+                self.push_first_leaf(parent.right);
+            }
+        }
+
+        Some(&self.storage.get(node_key).data)
+    }
+}
+
+/// A position in a tree that steps through its values in order, from `Tree::cursor_min`
+///
+/// Steps follow the parent links, so walking the whole tree is O(1) amortised per
+/// step. Stepping past either end leaves the cursor on no value, where it stays
+pub struct Cursor<'a, T> {
+    tree: &'a Tree<T>,
+    current: Option<SlotKey>,
+}
+
+impl<T> Clone for Cursor<'_, T> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree,
+            current: self.current,
+        }
+    }
+}
+
+impl<'a, T> Cursor<'a, T> {
+    /// Get the value the cursor is on, or `None` once it has stepped past an end
+    pub fn current(&self) -> Option<&'a T> {
+        Some(&self.tree.storage.get(self.current?).data)
+    }
+
+    /// Get the key of the node the cursor is on
+    pub fn current_key(&self) -> Option<SlotKey> {
+        self.current
+    }
+
+    /// Step to the next larger value
+    pub fn move_next(&mut self) {
+        self.current = self
+            .current
+            .and_then(|node_key| self.tree.next_key(node_key));
+    }
+
+    /// Step to the next smaller value
+    pub fn move_prev(&mut self) {
+        self.current = self
+            .current
+            .and_then(|node_key| self.tree.prev_key(node_key));
+    }
+
+    /// Get the next larger value without moving
+    pub fn peek_next(&self) -> Option<&'a T> {
+        let next_key = self.tree.next_key(self.current?)?;
+
+        Some(&self.tree.storage.get(next_key).data)
+    }
+
+    /// Get the next smaller value without moving
+    pub fn peek_prev(&self) -> Option<&'a T> {
+        let prev_key = self.tree.prev_key(self.current?)?;
+
+        Some(&self.tree.storage.get(prev_key).data)
+    }
+}
+
+/// A position in a tree like `Cursor` that can also remove the value it is on, from
+/// `Tree::cursor_min_mut`
+///
+/// This is the way to delete while walking the tree, as the iterators only borrow it
+pub struct CursorMut<'a, T> {
+    tree: &'a mut Tree<T>,
+    current: Option<SlotKey>,
+}
+
+impl<T> CursorMut<'_, T> {
+    /// Get the value the cursor is on, or `None` once it has stepped past an end
+    pub fn current(&self) -> Option<&T> {
+        Some(&self.tree.storage.get(self.current?).data)
+    }
+
+    /// Get the key of the node the cursor is on
+    pub fn current_key(&self) -> Option<SlotKey> {
+        self.current
+    }
+
+    /// Step to the next larger value
+    pub fn move_next(&mut self) {
+        self.current = self
+            .current
+            .and_then(|node_key| self.tree.next_key(node_key));
+    }
+
+    /// Step to the next smaller value
+    pub fn move_prev(&mut self) {
+        self.current = self
+            .current
+            .and_then(|node_key| self.tree.prev_key(node_key));
+    }
+
+    /// Remove the value the cursor is on, moving the cursor to the next larger value
+    ///
+    /// Removing the largest value leaves the cursor on no value, just like stepping
+    /// past the end. Nodes are relinked rather than moved when removing, so the
+    /// keys of every other node stay valid. Gives `None` if the cursor is on no value
+    pub fn remove_current(&mut self) -> Option<T> {
+        let node_key = self.current?;
+        self.current = self.tree.next_key(node_key);
+
+        Some(self.tree.remove_key(node_key))
+    }
+}
+
+impl<T> Tree<T>
+where
+    T: Eq + Ord + Debug,
+{
+    /// Build a balanced tree from values in ascending order
+    ///
+    /// The middle value of each range becomes the root of that range, so the
+    /// height is the minimum possible for the number of values. This is O(n)
+    ///
+    /// # NOTE
+    ///
+    /// The values must be strictly ascending, which is only checked in debug builds
+    pub fn from_sorted(sorted: Vec<T>) -> Self {
+        debug_assert!(
+            sorted.windows(2).all(|pair| pair[0] < pair[1]),
+            "Values must be strictly ascending"
+        );
+
+        let mut tree = Tree::with_capacity(sorted.len());
+        let mut values: Vec<Option<T>> = sorted.into_iter().map(Some).collect();
+
+        // Each entry is a range of values still to place, and where its root hangs
+        let mut stack = vec![(0, values.len(), None)];
+
+        while let Some((start, end, parent)) = stack.pop() {
+            if start == end {
+                continue;
+            }
+
+            let middle = start + (end - start) / 2;
+            let data = values[middle].take().expect("Every value is placed once");
+            let node_key = tree.storage.insert(TreeNode {
+                size: end - start,
+                ..TreeNode::new(data)
+            });
+            tree.link(parent, Some(node_key));
+
+            stack.push((middle + 1, end, Some((node_key, TreeDirection::Right))));
+            stack.push((start, middle, Some((node_key, TreeDirection::Left))));
+        }
+
+        tree
+    }
+
+    /// Build a balanced tree from an iterator of values in ascending order, without
+    /// collecting them first
+    ///
+    /// The length is known up front, so the shape is the same as `from_sorted` gives
+    /// and the nodes are made in order as the values arrive. This is O(n) with
+    /// O(log n) extra space
+    ///
+    /// # NOTE
+    ///
+    /// The values must be strictly ascending, which is only checked in debug builds
+    ///
+    /// # Panics
+    ///
+    /// If the iterator yields fewer values than its length said
+    pub fn from_sorted_iter<I>(iter: I) -> Self
+    where
+        I: ExactSizeIterator<Item = T>,
+    {
+        /// A range of values whose root is made once its left side is built
+        struct Frame {
+            start: usize,
+            end: usize,
+            /// `None` while the left side is being built
+            node_key: Option<SlotKey>,
+        }
+
+        /// Queue the range and the left ranges under it, down to an empty one
+        fn push_left_ranges(stack: &mut Vec<Frame>, start: usize, mut end: usize) {
+            while start < end {
+                stack.push(Frame {
+                    start,
+                    end,
+                    node_key: None,
+                });
+                end = start + (end - start) / 2;
+            }
+        }
+
+        let mut iter = iter;
+        let mut tree = Tree::with_capacity(iter.len());
+        let mut stack = Vec::new();
+        push_left_ranges(&mut stack, 0, iter.len());
+
+        // The root of the subtree built last, `None` for an empty range
+        let mut built = None;
+        let mut last_key: Option<SlotKey> = None;
+
+        while let Some(frame) = stack.last_mut() {
+            if let Some(node_key) = frame.node_key {
+                // Both sides are built, so this subtree is finished
+                attach(&mut tree.storage, node_key, TreeDirection::Right, built);
+                built = Some(node_key);
+                stack.pop();
+                continue;
+            }
+
+            let data = iter
+                .next()
+                .expect("The iterator must yield as many values as its length");
+            debug_assert!(
+                last_key.is_none_or(|last_key| tree.storage.get(last_key).data < data),
+                "Values must be strictly ascending"
+            );
+
+            let node_key = tree.storage.insert(TreeNode {
+                size: frame.end - frame.start,
+                ..TreeNode::new(data)
+            });
+            attach(&mut tree.storage, node_key, TreeDirection::Left, built);
+            frame.node_key = Some(node_key);
+            last_key = Some(node_key);
+
+            let (start, end) = (frame.start + (frame.end - frame.start) / 2 + 1, frame.end);
+            push_left_ranges(&mut stack, start, end);
+            built = None;
+        }
+
+        tree.root = built;
+
+        tree
+    }
+
+    /// Build a balanced tree from values in any order, dropping repeats
+    ///
+    /// The values are sorted once and handed to `from_sorted`, so this is O(n log n)
+    /// and always gives a tree of minimum height. Inserting the same values one at a
+    /// time keeps whatever shape the order produces, which for sorted or nearly
+    /// sorted input is close to a linked list
+    pub fn from_unsorted(mut items: Vec<T>) -> Self {
+        items.sort();
+        items.dedup();
+
+        Tree::from_sorted(items)
+    }
+
+    /// Rebuild a BST from its preorder listing, reproducing the original shape
+    ///
+    /// Each value narrows the valid range of the values after it, which is tracked
+    /// with a stack of the ancestors still able to take a right child, so this is O(n).
+    /// An empty listing gives an empty tree
+    pub fn from_preorder(preorder: Vec<T>) -> Self {
+        let mut items = preorder.into_iter();
+
+        let Some(root) = items.next() else {
+            return Tree::empty();
+        };
+
+        let mut tree = Tree::new(root);
+
+        // Ancestors of the last inserted node, the top being the closest
+        let mut stack: Vec<SlotKey> = tree.root.into_iter().collect();
+
+        for data in items {
+            let new_node = tree.storage.insert(TreeNode::new(data));
+            let data = &tree.storage.get(new_node).data;
+
+            // Climb to the highest ancestor smaller than this value, as the value
+            // must be its right child, otherwise it is the left child of the top
+            let mut parent = None;
+            while let Some(&top) = stack.last() {
+                if tree.storage.get(top).data >= *data {
+                    break;
+                }
+
+                parent = stack.pop();
+            }
+
+            match parent {
+                Some(parent) => attach(
+                    &mut tree.storage,
+                    parent,
+                    TreeDirection::Right,
+                    Some(new_node),
+                ),
+                None => {
+                    let top = *stack.last().expect("Root is only popped for a right child");
+                    attach(&mut tree.storage, top, TreeDirection::Left, Some(new_node));
+                }
+            }
+
+            stack.push(new_node);
+        }
+
+        tree.update_all_sizes();
+        tree
+    }
+
+    /// Insert data into the tree, placing it in an ordered location
+    ///
+    /// # NOTE
+    ///
+    /// If the data already exists in the tree, what happens depends on the
+    /// duplicate policy. `Reject` returns the new data as `InsertError::Duplicate`,
+    /// `Replace` stores the new data and returns the old one that way, and `Ignore`
+    /// drops the new data and returns `Ok`. A tree at its maximum length returns
+    /// new data as `InsertError::Full`, and one that would grow past its maximum
+    /// height returns it as `InsertError::TooDeep`
+    pub fn insert_ordered(&mut self, data: T) -> Result<(), InsertError<T>> {
+        self.insert_counting(data).0
+    }
+
+    /// Insert data into the tree, returning whether it was newly added
+    ///
+    /// This follows `BTreeSet::insert`, so `false` is returned whenever an equal
+    /// value was already present, with the duplicate policy deciding which is kept
+    pub fn insert(&mut self, data: T) -> bool {
+        let old_len = self.len();
+        let _ = self.insert_ordered(data);
+
+        self.len() > old_len
+    }
+
+    /// Insert data into the tree, reporting what happened without handing back values
+    ///
+    /// The duplicate policy still decides which of two equal values is kept, but
+    /// every duplicate is reported as `InsertOutcome::Duplicate`. Use
+    /// `insert_ordered_key` to get back the value that was left out
+    pub fn try_insert(&mut self, data: T) -> InsertOutcome {
+        let old_len = self.len();
+
+        match self.insert_ordered_key(data) {
+            Ok(node_key) if self.len() > old_len => InsertOutcome::Inserted(node_key),
+            Ok(_) | Err(InsertError::Duplicate(_)) => InsertOutcome::Duplicate,
+            Err(InsertError::Full(_)) => InsertOutcome::Full,
+            Err(InsertError::TooDeep(_)) => InsertOutcome::TooDeep,
+            Err(InsertError::Incomparable(_)) => unreachable!("Ord values can always be compared"),
+        }
+    }
+
+    /// Insert data known not to be in the tree, returning the key of its new node
+    ///
+    /// This skips the equality check on the way down, the duplicate policy and the
+    /// maximum length, which are all only checked in debug builds. The maximum
+    /// height is not checked at all
+    ///
+    /// # WARNING
+    ///
+    /// Inserting a value that is already present cannot cause undefined behaviour,
+    /// but it leaves two equal values in the tree. Searches will then only find one
+    /// of them, and removing it leaves the other behind
+    pub fn insert_unchecked(&mut self, data: T) -> SlotKey {
+        debug_assert!(!self.at_max_len(), "Tree is at its maximum length");
+
+        let mut parent = None;
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            let current_node = self.storage.get(current_key);
+            let direction = self.settings.search_step(&data, &current_node.data);
+            debug_assert!(direction.is_some(), "Data is already in the tree");
+            let direction = direction.unwrap_or(TreeDirection::Right);
+
+            parent = Some((current_key, direction));
+            current = current_node.child(direction);
+        }
+
+        let new_key = self.storage.insert(TreeNode::new(data));
+        self.link(parent, Some(new_key));
+        self.update_sizes_upward(parent.map(|(parent_key, _)| parent_key));
+
+        new_key
+    }
+
+    /// Insert items one at a time until `stop` returns true or the items run out
+    ///
+    /// `stop` is checked after every item, so an endless iterator is fine as long
+    /// as it eventually says to stop. Returns how many items were newly added,
+    /// with duplicates handled by the duplicate policy and not counted
+    pub fn insert_from<I, F>(&mut self, items: I, stop: F) -> usize
+    where
+        I: IntoIterator<Item = T>,
+        F: Fn(&Tree<T>) -> bool,
+    {
+        let mut inserted = 0;
+
+        for item in items {
+            if self.insert(item) {
+                inserted += 1;
+            }
+
+            if stop(self) {
+                break;
+            }
+        }
+
+        inserted
+    }
+
+    /// Insert every item with `insert_ordered`, returning the values handed back
+    ///
+    /// Under the default `Reject` policy these are the duplicates, in the order they
+    /// came. Any value refused by `with_max_len` or `with_max_height` is included too,
+    /// so for unique items into an unlimited tree this is empty
+    pub fn insert_all<I>(&mut self, items: I) -> Vec<T>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        items
+            .into_iter()
+            .filter_map(|item| self.insert_ordered(item).err())
+            .map(InsertError::into_inner)
+            .collect()
+    }
+
+    /// Insert data like `insert_ordered`, also returning how many nodes it was compared against
+    ///
+    /// Each node passed on the way down counts as one comparison, so this is the
+    /// depth the search reached and shows how a degenerate tree makes inserts slower
+    pub fn insert_counting(&mut self, data: T) -> (Result<(), InsertError<T>>, usize) {
+        let (result, comparisons) = self.insert_counting_key(data);
+
+        (result.map(|_| ()), comparisons)
+    }
+
+    /// Build a tree by inserting each item in turn, also returning the total number of
+    /// comparisons made
+    ///
+    /// Comparisons are counted as in `insert_counting`, so sorted input, which makes
+    /// every insert walk the whole tree, shows its O(n²) cost next to shuffled input.
+    /// Items turned away as duplicates still count the comparisons used to find them
+    pub fn build_with_comparison_count<I>(items: I) -> (Self, u64)
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut tree = Tree::empty();
+        let mut total = 0;
+
+        for item in items {
+            let (_, comparisons) = tree.insert_counting(item);
+            total += comparisons as u64;
+        }
+
+        (tree, total)
+    }
+
+    /// Insert data like `insert_ordered`, returning the key of the node now holding it
+    ///
+    /// Under the `Ignore` duplicate policy the key of the equal value already in
+    /// the tree is returned, as that node is the one kept
+    pub fn insert_ordered_key(&mut self, data: T) -> Result<SlotKey, InsertError<T>> {
+        self.insert_counting_key(data).0
+    }
+
+    /// The insert behind all of the others, giving the key of the node holding the
+    /// data and how many nodes it was compared against
+    fn insert_counting_key(&mut self, data: T) -> (Result<SlotKey, InsertError<T>>, usize) {
+        // Store the current node we are viewing
+        let Some(mut current_key) = self.root else {
+            if self.at_max_len() {
+                return (Err(InsertError::Full(data)), 0);
+            }
+            if self.too_deep(0) {
+                return (Err(InsertError::TooDeep(data)), 0);
+            }
+
+            // An empty tree just takes the data as its root
+            let new_node = self.storage.insert(TreeNode::new(data));
+            self.root = Some(new_node);
+            return (Ok(new_node), 0);
+        };
+        let insert_direction: TreeDirection;
+        let mut comparisons = 0;
+
+        // Locate the location to insert into
+        loop {
+            let current_node = self.storage.get(current_key);
+            comparisons += 1;
+
+            let Some(direction) = self.settings.search_step(&data, &current_node.data) else {
+                return (self.insert_duplicate(current_key, data), comparisons);
+            };
+
+            if let Some(next_key) = current_node.child(direction) {
+                current_key = next_key;
+                continue;
+            }
+
+            // This is a leaf node on that side
+            // Insert here
+            insert_direction = direction;
+            break;
+        }
+
+        // Duplicates never add a node, so the limit only matters once we know this is new
+        if self.at_max_len() {
+            return (Err(InsertError::Full(data)), comparisons);
+        }
+        // Every node compared against is above the new one
+        if self.too_deep(comparisons) {
+            return (Err(InsertError::TooDeep(data)), comparisons);
+        }
+
+        // Create a new node with our data
+        let new_node = self.storage.insert(TreeNode::new(data));
+
+        // Hang it off the last node
+        attach(
+            &mut self.storage,
+            current_key,
+            insert_direction,
+            Some(new_node),
+        );
+        self.update_sizes_upward(Some(current_key));
+        // The new node sits one level below every node it was compared against
+        self.rebalance_if_tall(comparisons + 1);
+
+        (Ok(new_node), comparisons)
+    }
+
+    /// Check if tree contains
+    pub fn contains(&self, data: &T) -> bool {
+        // Store the current node we are viewing
+        let Some(mut current_key) = self.root else {
+            return false;
+        };
+
+        // Locate the location to insert into
+        loop {
+            let current_node = self.storage.get(current_key);
+
+            let Some(direction) = self.settings.search_step(data, &current_node.data) else {
+                // It exists
+                return true;
+            };
+
+            if let Some(next_key) = current_node.child(direction) {
+                current_key = next_key;
+                continue;
+            }
+
+            // This is a leaf node on that side
+            // It must not exist then
+            return false;
+        }
+    }
+
+    /// Check if tree contains, also returning how many nodes it was compared against
+    ///
+    /// Each node passed on the way down counts as one comparison, so a degenerate
+    /// tree shows up as a count close to `len()` rather than close to log2 of it
+    pub fn contains_counting(&self, data: &T) -> (bool, usize) {
+        let mut comparisons = 0;
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            let current_node = self.storage.get(current_key);
+            comparisons += 1;
+
+            match self.settings.search_step(data, &current_node.data) {
+                Some(direction) => current = current_node.child(direction),
+                None => return (true, comparisons),
+            }
+        }
+
+        (false, comparisons)
+    }
+
+    /// Check if tree contains, giving up after comparing against `max_steps` nodes
+    ///
+    /// Gives `None` if the search would have had to go deeper, so a lookup in a
+    /// degenerate part of the tree has a bounded cost. Reaching a missing child
+    /// within the budget is a conclusive `Some(false)`
+    pub fn contains_within(&self, data: &T, max_steps: usize) -> Option<bool> {
+        let mut current = self.root;
+
+        for _ in 0..max_steps {
+            let Some(current_key) = current else {
+                return Some(false);
+            };
+            let current_node = self.storage.get(current_key);
+
+            match self.settings.search_step(data, &current_node.data) {
+                Some(direction) => current = current_node.child(direction),
+                None => return Some(true),
+            }
+        }
+
+        // The budget ran out, but the search may have just left the tree anyway
+        current.is_none().then_some(false)
+    }
+
+    /// Find the node holding data and its depth, where the root is at depth zero
+    pub fn find_with_depth(&self, data: &T) -> Option<(SlotKey, usize)> {
+        let mut depth = 0;
+        let mut current_key = self.root?;
+
+        loop {
+            let current_node = self.storage.get(current_key);
+
+            match self.settings.search_step(data, &current_node.data) {
+                Some(direction) => current_key = current_node.child(direction)?,
+                None => return Some((current_key, depth)),
+            }
+            depth += 1;
+        }
+    }
+
+    /// Find the node holding data, or where data would be inserted if it is absent
+    ///
+    /// Like `slice::binary_search`, `Err` gives the leaf parent and the side of it the
+    /// new node would hang from, so a caller can decide whether to insert without
+    /// searching again. An empty tree gives `Err((None, TreeDirection::Left))`, as
+    /// the data would become the root
+    pub fn search(&self, data: &T) -> Result<SlotKey, (Option<SlotKey>, TreeDirection)> {
+        let Some(mut current_key) = self.root else {
+            return Err((None, TreeDirection::Left));
+        };
+
+        loop {
+            let current_node = self.storage.get(current_key);
+
+            let Some(direction) = self.settings.search_step(data, &current_node.data) else {
+                return Ok(current_key);
+            };
+
+            match current_node.child(direction) {
+                Some(next_key) => current_key = next_key,
+                None => return Err((Some(current_key), direction)),
+            }
+        }
+    }
+
+    /// Get the stored value equal to data, or an error naming data if it is not in the tree
+    ///
+    /// This is for passing a descriptive error up with `?`, where `find_with_depth`
+    /// would need turning from an `Option` by hand
+    pub fn try_get(&self, data: &T) -> Result<&T, TreeError> {
+        let (node_key, _) = self
+            .find_with_depth(data)
+            .ok_or_else(|| TreeError::NotFound(format!("{:?}", data)))?;
+
+        Ok(&self.storage.get(node_key).data)
+    }
+
+    /// Find the steps taken from the root to reach the node holding this data
+    ///
+    /// An empty path means the data is at the root, and `None` means it is not in the tree
+    pub fn path_to(&self, data: &T) -> Option<Vec<TreeDirection>> {
+        let mut path = Vec::new();
+        let mut current_key = self.root?;
+
+        loop {
+            let current_node = self.storage.get(current_key);
+
+            let Some(direction) = self.settings.search_step(data, &current_node.data) else {
+                return Some(path);
+            };
+            let next_key = current_node.child(direction);
+
+            // Reaching a leaf means the data does not exist
+            current_key = next_key?;
+            path.push(direction);
+        }
+    }
+
+    /// Count the nodes from the root that the searches for `a` and `b` both visit
+    ///
+    /// This is the depth of the node where the searches split, plus one. Neither value
+    /// has to be in the tree, and an empty tree gives zero
+    pub fn shared_path_len(&self, a: &T, b: &T) -> usize {
+        let mut shared = 0;
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            let current_node = self.storage.get(current_key);
+            shared += 1;
+
+            let step_a = self.settings.search_step(a, &current_node.data);
+            let step_b = self.settings.search_step(b, &current_node.data);
+
+            current = match (step_a, step_b) {
+                (Some(direction_a), Some(direction_b)) if direction_a == direction_b => {
+                    current_node.child(direction_a)
+                }
+                _ => None,
+            };
+        }
+
+        shared
+    }
+
+    /// Find the node holding this data, along with its parent and which side of the parent it is on
+    fn find_with_parent(&self, data: &T) -> Option<(SlotKey, Option<(SlotKey, TreeDirection)>)> {
+        let mut parent = None;
+        let mut current_key = self.root?;
+
+        loop {
+            let current_node = self.storage.get(current_key);
+
+            let Some(direction) = self.settings.search_step(data, &current_node.data) else {
+                return Some((current_key, parent));
+            };
+            let next_key = current_node.child(direction);
+
+            // Reaching a leaf means the data does not exist
+            parent = Some((current_key, direction));
+            current_key = next_key?;
+        }
+    }
+
+    /// Remove and return the value equal to data, but only if `f` accepts it
+    ///
+    /// The value is found with a single descent and removed in place, so the keys
+    /// of the other nodes stay valid. If data is absent `f` is not called
+    pub fn take_if<F>(&mut self, data: &T, f: F) -> Option<T>
+    where
+        F: FnOnce(&T) -> bool,
+    {
+        let (node_key, _) = self.find_with_parent(data)?;
+
+        if !f(&self.storage.get(node_key).data) {
+            return None;
+        }
+        let data = self.remove_key(node_key);
+        self.compact_if_sparse();
+
+        Some(data)
+    }
+
+    /// Copy the subtree rooted at the node holding this data into a new tree
+    pub fn subtree(&self, data: &T) -> Option<Tree<T>>
+    where
+        T: Clone,
+    {
+        let (subtree_root, _) = self.find_with_parent(data)?;
+
+        let root_node = self.storage.get(subtree_root);
+        let mut storage = SlotMap::new();
+        let new_root = storage.insert(TreeNode {
+            size: root_node.size,
+            ..TreeNode::new(root_node.data.clone())
+        });
+        let mut tree = Tree {
+            storage,
+            root: Some(new_root),
+            settings: self.settings,
+            sorted_cache: None,
+        };
+
+        // Each entry is a node still to copy, and the copied parent it belongs under
+        let mut stack = Vec::new();
+        push_children(&mut stack, (root_node.left, root_node.right), new_root);
+
+        while let Some((old_key, new_parent, direction)) = stack.pop() {
+            let old_node = self.storage.get(old_key);
+            let new_key = tree.storage.insert(TreeNode {
+                size: old_node.size,
+                ..TreeNode::new(old_node.data.clone())
+            });
+
+            attach(&mut tree.storage, new_parent, direction, Some(new_key));
+
+            push_children(&mut stack, (old_node.left, old_node.right), new_key);
+        }
+
+        Some(tree)
+    }
+
+    /// Copy the values under the node holding this data into a new balanced tree
+    ///
+    /// Unlike `subtree` the shape of the original is not kept, so a skewed subtree
+    /// still gives a tree of minimum height
+    pub fn subtree_balanced(&self, data: &T) -> Option<Tree<T>>
+    where
+        T: Clone,
+    {
+        let (subtree_root, _) = self.find_with_parent(data)?;
+
+        let mut values = InOrderIter {
+            storage: &self.storage,
+            stack: Vec::new(),
+        };
+        values.push_left_spine(Some(subtree_root));
+
+        Some(Tree {
+            settings: self.settings,
+            ..Tree::from_sorted(values.cloned().collect())
+        })
+    }
+
+    /// Detach the subtree rooted at the node holding this data and return it as its own tree
+    ///
+    /// The detached nodes are removed from this tree's storage, and pruning the
+    /// root leaves this tree empty
+    pub fn prune(&mut self, data: &T) -> Option<Tree<T>> {
+        let (subtree_root, parent) = self.find_with_parent(data)?;
+
+        let Some((parent_key, direction)) = parent else {
+            // The whole tree is being pruned
+            let empty = self.empty_like();
+            return Some(core::mem::replace(self, empty));
+        };
+
+        // Unlink the subtree from its parent before moving it out
+        self.storage.get_mut(parent_key).set_child(direction, None);
+        self.update_sizes_upward(Some(parent_key));
+
+        Some(self.take_subtree(subtree_root))
+    }
+
+    /// Move an already detached subtree out of this tree's storage into a tree of its own
+    fn take_subtree(&mut self, subtree_root: SlotKey) -> Tree<T> {
+        let mut storage = SlotMap::new();
+        let new_root = move_subtree(&mut self.storage, subtree_root, &mut storage);
+
+        Tree {
+            storage,
+            root: Some(new_root),
+            settings: self.settings,
+            sorted_cache: None,
+        }
+    }
+
+    /// Get the smallest value in the tree
+    pub fn min(&self) -> Option<&T> {
+        Some(&self.storage.get(self.min_key()?).data)
+    }
+
+    /// Get the largest value in the tree
+    pub fn max(&self) -> Option<&T> {
+        Some(&self.storage.get(self.max_key()?).data)
+    }
+
+    /// Get the smallest and largest values together, which are the same for a single value
+    pub fn min_max(&self) -> Option<(&T, &T)> {
+        Some((self.min()?, self.max()?))
+    }
+
+    /// Split the tree into one holding the values less than `pivot`, and one holding the rest
+    ///
+    /// This walks a single path down from the root, relinking each node into the side
+    /// it belongs to so both trees keep the shape of the original. The values less than the
+    /// pivot stay in this tree's storage and the rest are moved into new storage, so overall
+    /// it is O(height + n) where n is the number of values greater than or equal to the pivot
+    pub fn split(mut self, pivot: &T) -> (Tree<T>, Tree<T>) {
+        // Where the next node belonging to each side gets attached,
+        // `None` meaning it becomes that side's root
+        let mut less_hook: Option<(SlotKey, TreeDirection)> = None;
+        let mut greater_hook: Option<(SlotKey, TreeDirection)> = None;
+        let mut less_root = None;
+        let mut greater_root = None;
+
+        // Only the nodes on the walked path end up with different subtrees
+        let mut path = Vec::new();
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            path.push(current_key);
+
+            let current_node = self.storage.get(current_key);
+
+            let below = self.settings.compare(&current_node.data, pivot) == Ordering::Less;
+            let (hook, side_root, direction, next) = if below {
+                // This node and its left subtree are all less, only the right needs splitting
+                (
+                    &mut less_hook,
+                    &mut less_root,
+                    TreeDirection::Right,
+                    current_node.right,
+                )
+            } else {
+                // This node and its right subtree are all greater, only the left needs splitting
+                (
+                    &mut greater_hook,
+                    &mut greater_root,
+                    TreeDirection::Left,
+                    current_node.left,
+                )
+            };
+
+            match *hook {
+                Some((parent, parent_direction)) => attach(
+                    &mut self.storage,
+                    parent,
+                    parent_direction,
+                    Some(current_key),
+                ),
+                None => {
+                    self.storage.get_mut(current_key).parent = None;
+                    *side_root = Some(current_key);
+                }
+            }
+
+            *hook = Some((current_key, direction));
+            current = next;
+        }
+
+        // The last node on each side keeps a pointer into the other side, cut it
+        for (parent, direction) in [less_hook, greater_hook].into_iter().flatten() {
+            self.storage.get_mut(parent).set_child(direction, None);
+        }
+
+        // Deepest first, so each node's children are up to date before it
+        for node_key in path.into_iter().rev() {
+            self.update_size(node_key);
+        }
+
+        let greater = match greater_root {
+            Some(greater_root) => self.take_subtree(greater_root),
+            None => self.empty_like(),
+        };
+
+        self.root = less_root;
+
+        (self, greater)
+    }
+
+    /// Join two trees, where every value in this tree is less than every value in `other`
+    ///
+    /// `other` is hung off the right of this tree's largest node, so both keep their shape
+    /// and this is O(height + m) where m is the size of `other`.
+    /// If the precondition does not hold the trees are handed back unchanged
+    // Handing the trees back by value is the point of the error, so it is allowed to be large
+    #[allow(clippy::result_large_err)]
+    pub fn merge(mut self, mut other: Tree<T>) -> Result<Tree<T>, (Tree<T>, Tree<T>)> {
+        let (Some(self_max), Some(other_min)) = (self.max(), other.min()) else {
+            // One side is empty, so the other is the answer
+            return Ok(if self.root.is_none() { other } else { self });
+        };
+
+        if self.settings.compare(self_max, other_min) != Ordering::Less {
+            return Err((self, other));
+        }
+
+        // Find the largest node, which has no right child to get in the way
+        let mut max_key = self.root.expect("Not empty, as it has a max");
+        while let Some(right_node) = self.storage.get(max_key).right {
+            max_key = right_node;
+        }
+
+        let other_root = other.root.expect("Not empty, as it has a min");
+        let moved_root = move_subtree(&mut other.storage, other_root, &mut self.storage);
+
+        attach(
+            &mut self.storage,
+            max_key,
+            TreeDirection::Right,
+            Some(moved_root),
+        );
+        self.update_sizes_upward(Some(max_key));
+
+        Ok(self)
+    }
+
+    /// Hang a whole tree off one side of the node holding `parent`, moving its nodes in
+    ///
+    /// Values are moved rather than cloned, and the subtree keeps its shape. The subtree
+    /// is handed back unchanged if `parent` is absent, already has a child on that side,
+    /// or the subtree's values do not all fit between `parent` and its neighbour on that
+    /// side. Grafting an empty tree always succeeds and changes nothing
+    // Handing the subtree back by value is the point of the error, so it is allowed to be large
+    #[allow(clippy::result_large_err)]
+    pub fn graft(
+        &mut self,
+        parent: &T,
+        direction: TreeDirection,
+        mut subtree: Tree<T>,
+    ) -> Result<(), Tree<T>> {
+        let (Some(subtree_root), Some(subtree_min), Some(subtree_max)) =
+            (subtree.root, subtree.min(), subtree.max())
+        else {
+            return Ok(());
+        };
+
+        let Some((parent_key, _)) = self.find_with_parent(parent) else {
+            return Err(subtree);
+        };
+        let parent_node = self.storage.get(parent_key);
+        if parent_node.child(direction).is_some() {
+            return Err(subtree);
+        }
+
+        // With that side empty, the neighbour on it is the nearest ancestor past the parent
+        let neighbour = self
+            .step_key(parent_key, direction)
+            .map(|neighbour_key| &self.storage.get(neighbour_key).data);
+        let less = |a: &T, b: &T| self.settings.compare(a, b) == Ordering::Less;
+        let fits = match direction {
+            TreeDirection::Left => {
+                less(subtree_max, &parent_node.data)
+                    && neighbour.is_none_or(|lower| less(lower, subtree_min))
+            }
+            TreeDirection::Right => {
+                less(&parent_node.data, subtree_min)
+                    && neighbour.is_none_or(|upper| less(subtree_max, upper))
+            }
+        };
+        if !fits {
+            return Err(subtree);
+        }
+
+        let moved_root = move_subtree(&mut subtree.storage, subtree_root, &mut self.storage);
+        attach(&mut self.storage, parent_key, direction, Some(moved_root));
+        self.update_sizes_upward(Some(parent_key));
+
+        Ok(())
+    }
+
+    /// Consume the tree, moving every value out in ascending order
+    pub fn into_sorted_vec(mut self) -> Vec<T> {
+        let mut out = Vec::with_capacity(self.storage.item_count);
+
+        // Nodes whose left subtree is still being emptied, the top being the deepest
+        let mut stack = Vec::new();
+        let mut current = self.root;
+
+        loop {
+            // Walk down to the smallest node not yet taken
+            while let Some(current_key) = current {
+                stack.push(current_key);
+                current = self.storage.get(current_key).left;
+            }
+
+            let Some(node_key) = stack.pop() else {
+                break;
+            };
+
+            // Everything to the left has been taken, so this node is next
+            let node = self.storage.remove(node_key);
+            out.push(node.data);
+            current = node.right;
+        }
+
+        out
+    }
+
+    /// Remove every value found in a sorted slice, returning how many were removed
+    ///
+    /// The values are walked in order alongside the slice and the survivors rebuilt
+    /// into a balanced tree, so this is O(n + k) however many values are removed.
+    /// Values in the slice that are not in the tree are skipped
+    ///
+    /// # NOTE
+    ///
+    /// Every node is rebuilt, so no earlier key stays valid
+    pub fn bulk_remove(&mut self, sorted: &[T]) -> usize {
+        let settings = self.settings;
+        let old_len = self.len();
+        let values = core::mem::replace(self, Tree::empty()).into_sorted_vec();
+
+        let mut removals = sorted.iter().peekable();
+        let kept = values
+            .into_iter()
+            .filter(|value| {
+                // Skip past removals smaller than this value
+                while removals
+                    .next_if(|removal| {
+                        settings.search_step(value, removal) == Some(TreeDirection::Right)
+                    })
+                    .is_some()
+                {}
+
+                removals
+                    .peek()
+                    .is_none_or(|removal| settings.search_step(value, removal).is_some())
+            })
+            .collect();
+
+        *self = Tree {
+            settings,
+            ..Tree::from_sorted(kept)
+        };
+
+        old_len - self.len()
+    }
+
+    /// Remove every value that is also in `other`, reusing this tree's storage
+    ///
+    /// Both trees are walked in order side by side and the surviving nodes relinked
+    /// into a balanced shape, so this is O(n + m) and allocates only the list of
+    /// survivors. Unlike `bulk_remove` nothing is rebuilt, so the surviving nodes keep
+    /// their keys
+    pub fn subtract_in_place(&mut self, other: &Tree<T>) {
+        let settings = self.settings;
+        let mut others = other
+            .in_order_keys()
+            .map(|node_key| &other.storage.get(node_key).data)
+            .peekable();
+
+        let (kept, removed): (Vec<SlotKey>, Vec<SlotKey>) =
+            self.in_order_keys().partition(|&node_key| {
+                let data = &self.storage.get(node_key).data;
+
+                // Skip past values of the other tree smaller than this one
+                while others
+                    .next_if(|other_data| {
+                        settings.search_step(data, other_data) == Some(TreeDirection::Right)
+                    })
+                    .is_some()
+                {}
+
+                others
+                    .peek()
+                    .is_none_or(|other_data| settings.search_step(data, other_data).is_some())
+            });
+
+        if removed.is_empty() {
+            return;
+        }
+
+        for node_key in removed {
+            self.storage.remove(node_key);
+        }
+        self.relink_balanced(&kept);
+        self.compact_if_sparse();
+    }
+
+    /// Remove every value that `f` returns false for, rebuilding the survivors balanced
+    ///
+    /// Unlike `retain`, which removes nodes in place and can leave a heavy prune
+    /// lopsided, the survivors are collected in order and rebuilt with `from_sorted`.
+    /// This is O(n) and the settings are kept
+    ///
+    /// # NOTE
+    ///
+    /// Every node is rebuilt, so no earlier key stays valid
+    pub fn retain_balanced<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let settings = self.settings;
+        let mut values = core::mem::replace(self, Tree::empty()).into_sorted_vec();
+        values.retain(|value| f(value));
+
+        *self = Tree {
+            settings,
+            ..Tree::from_sorted(values)
+        };
+    }
+
+    /// Add a batch of ascending values, rebuilding the tree balanced in O(n + m)
+    ///
+    /// The batch is merged with the values already in the tree rather than inserted
+    /// one at a time, so a sorted batch cannot leave the tree degenerate. Values
+    /// already present, or repeated within the batch, are dropped
+    ///
+    /// # NOTE
+    ///
+    /// The batch must be in ascending order, which is only checked in debug builds
+    pub fn extend_sorted(&mut self, sorted: Vec<T>) {
+        debug_assert!(
+            sorted.windows(2).all(|pair| pair[0] <= pair[1]),
+            "Batch must be in ascending order"
+        );
+
+        let settings = self.settings;
+        let existing = core::mem::replace(self, Tree::empty()).into_sorted_vec();
+        let mut merged = Vec::with_capacity(existing.len() + sorted.len());
+
+        let mut existing = existing.into_iter().peekable();
+        let mut batch = sorted.into_iter().peekable();
+
+        loop {
+            let next = match (existing.peek(), batch.peek()) {
+                (Some(old), Some(new)) => {
+                    if new < old {
+                        batch.next()
+                    } else {
+                        existing.next()
+                    }
+                }
+                (Some(_), None) => existing.next(),
+                (None, Some(_)) => batch.next(),
+                (None, None) => break,
+            };
+            let next = next.expect("A value was peeked");
+
+            // Equal values arrive next to each other, so only the first is kept
+            if merged.last() != Some(&next) {
+                merged.push(next);
+            }
+        }
+
+        *self = Tree {
+            settings,
+            ..Tree::from_sorted(merged)
+        };
+    }
+
+    /// Combine many trees into one balanced tree, dropping repeated values
+    ///
+    /// The sorted values of every tree are merged at once through a min heap, which
+    /// is O(N log k) for N values across k trees. The result takes the settings of
+    /// the first tree
+    pub fn merge_all(trees: Vec<Tree<T>>) -> Tree<T> {
+        let settings = trees.first().map(|tree| tree.settings).unwrap_or_default();
+        let total: usize = trees.iter().map(Tree::len).sum();
+
+        let mut sources: Vec<_> = trees
+            .into_iter()
+            .map(|tree| tree.into_sorted_vec().into_iter())
+            .collect();
+
+        // Each entry is the next value from a source, and which source it came from
+        let mut heap = BinaryHeap::with_capacity(sources.len());
+        for (index, source) in sources.iter_mut().enumerate() {
+            if let Some(value) = source.next() {
+                heap.push(Reverse((value, index)));
+            }
+        }
+
+        let mut merged: Vec<T> = Vec::with_capacity(total);
+        while let Some(Reverse((value, index))) = heap.pop() {
+            if let Some(next) = sources[index].next() {
+                heap.push(Reverse((next, index)));
+            }
+
+            // Equal values come off the heap one after another, so only the first is kept
+            if merged.last() != Some(&value) {
+                merged.push(value);
+            }
+        }
+
+        Tree {
+            settings,
+            ..Tree::from_sorted(merged)
+        }
+    }
+
+    /// Rebuild the tree balanced in fresh storage, as a last resort after corruption
+    ///
+    /// Values are read breadth first from the root, and only nodes reachable from
+    /// the root survive. Any orphaned slots, links to empty slots and repeated
+    /// visits through a cycle are dropped, so the memory they held is reclaimed.
+    /// The values are sorted and repeats removed before building, so the result is
+    /// a valid BST even if the old links were not
+    pub fn rebuild(mut self) -> Self {
+        let bit_length = u64::BITS as usize;
+        let mut visited = vec![0_u64; self.storage.capacity().div_ceil(bit_length)];
+
+        let mut values = Vec::with_capacity(self.storage.item_count);
+        let mut queue = VecDeque::<SlotKey>::new();
+        queue.extend(self.root);
+
+        while let Some(current_key) = queue.pop_front() {
+            let (chunk, bit) = (
+                current_key.index / bit_length,
+                current_key.index % bit_length,
+            );
+            if self.node(current_key).is_none() || visited[chunk] & (1 << bit) != 0 {
+                continue;
+            }
+            visited[chunk] |= 1 << bit;
+
+            let current_node = self.storage.remove(current_key);
+            queue.extend(current_node.left);
+            queue.extend(current_node.right);
+            values.push(current_node.data);
+        }
+
+        Tree {
+            settings: self.settings,
+            ..Tree::from_unsorted(values)
+        }
+    }
+
+    /// Check if every value in this tree is also in `other`
+    ///
+    /// Both trees are walked in order side by side, which is O(n + m)
+    pub fn is_subset(&self, other: &Tree<T>) -> bool {
+        if self.len() > other.len() {
+            return false;
+        }
+
+        let mut others = other
+            .in_order_keys()
+            .map(|node_key| &other.storage.get(node_key).data);
+
+        self.in_order_keys().all(|node_key| {
+            let data = &self.storage.get(node_key).data;
+
+            // Skip past the smaller values of other, which this tree does not need
+            others
+                .by_ref()
+                .find(|other_data| self.settings.compare(other_data, data) != Ordering::Less)
+                .is_some_and(|other_data| {
+                    self.settings.compare(other_data, data) == Ordering::Equal
+                })
+        })
+    }
+
+    /// Walk the values of both trees in order together, saying which tree each is in
+    ///
+    /// This is a single O(n + m) pass, and the basis for a union, intersection or
+    /// difference, or for listing what changed between two versions of a set
+    pub fn merge_join<'a>(&'a self, other: &'a Tree<T>) -> impl Iterator<Item = MergeSide<'a, T>> {
+        let settings = self.settings;
+        let mut lefts = self.iter_in_order().peekable();
+        let mut rights = other.iter_in_order().peekable();
+
+        core::iter::from_fn(move || match (lefts.peek(), rights.peek()) {
+            (Some(left), Some(right)) => match settings.compare(left, right) {
+                Ordering::Less => lefts.next().map(MergeSide::Left),
+                Ordering::Greater => rights.next().map(MergeSide::Right),
+                Ordering::Equal => Some(MergeSide::Both(lefts.next()?, rights.next()?)),
+            },
+            (Some(_), None) => lefts.next().map(MergeSide::Left),
+            (None, Some(_)) => rights.next().map(MergeSide::Right),
+            (None, None) => None,
+        })
+    }
+
+    /// Check if every value in `other` is also in this tree
+    pub fn is_superset(&self, other: &Tree<T>) -> bool {
+        other.is_subset(self)
+    }
+
+    /// Check the tree holds exactly the distinct values of a reference, such as a `BTreeSet`
+    ///
+    /// The reference can be in any order and have repeats, as it is sorted and
+    /// deduplicated before being compared with the in order walk. This is meant as
+    /// the oracle for property tests that run the same operations on both
+    #[cfg(feature = "testing")]
+    pub fn agrees_with<I>(&self, reference: I) -> bool
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut reference: Vec<T> = reference.into_iter().collect();
+        reference.sort();
+        reference.dedup();
+
+        self.eq_sorted(&reference)
+    }
+
+    /// Check the tree holds exactly these values, compared in order
+    ///
+    /// The values are matched one by one during the walk, stopping at the first
+    /// difference, and a different length is caught before walking at all
+    pub fn eq_sorted(&self, expected: &[T]) -> bool {
+        if expected.len() != self.len() {
+            return false;
+        }
+
+        let mut expected = expected.iter();
+        let mut stack = Vec::new();
+        let mut current = self.root;
+
+        loop {
+            while let Some(current_key) = current {
+                stack.push(current_key);
+                current = self.storage.get(current_key).left;
+            }
+
+            let Some(node_key) = stack.pop() else {
+                return true;
+            };
+
+            let node = self.storage.get(node_key);
+            if expected.next() != Some(&node.data) {
+                return false;
+            }
+            current = node.right;
+        }
+    }
+
+    /// Look for data and rotate the node holding it up to the root
+    ///
+    /// Recently splayed values are cheap to find again, so calling this instead of
+    /// `contains` turns the tree into a self-adjusting splay tree. If the data is absent
+    /// the last node reached on the search is splayed instead, and `false` is returned
+    pub fn splay(&mut self, data: &T) -> bool {
+        // The nodes passed on the way down, and which way we went at each
+        let mut path: Vec<(SlotKey, TreeDirection)> = Vec::new();
+        let Some(mut current_key) = self.root else {
+            return false;
+        };
+
+        let found = loop {
+            let current_node = self.storage.get(current_key);
+
+            let direction = match self.settings.compare(data, &current_node.data) {
+                Ordering::Equal => break true,
+                Ordering::Less => TreeDirection::Left,
+                Ordering::Greater => TreeDirection::Right,
+            };
+
+            let Some(next_key) = current_node.child(direction) else {
+                break false;
+            };
+
+            path.push((current_key, direction));
+            current_key = next_key;
+        };
+
+        // Lift the node two levels at a time until it reaches the root
+        while let Some((parent_key, parent_direction)) = path.pop() {
+            let Some((grandparent_key, grandparent_direction)) = path.pop() else {
+                // Zig, the parent is the root so one rotation finishes
+                self.rotate(parent_key, parent_direction.opposite());
+                self.link(None, Some(current_key));
+                break;
+            };
+
+            if grandparent_direction == parent_direction {
+                // Zig-zig, rotate the parent up first then the node
+                let lifted = self.rotate(grandparent_key, grandparent_direction.opposite());
+                self.rotate(lifted, parent_direction.opposite());
+            } else {
+                // Zig-zag, rotate the node up past its parent then its grandparent
+                let lifted = self.rotate(parent_key, parent_direction.opposite());
+                attach(
+                    &mut self.storage,
+                    grandparent_key,
+                    grandparent_direction,
+                    Some(lifted),
+                );
+                self.rotate(grandparent_key, grandparent_direction.opposite());
+            }
+
+            self.link(path.last().copied(), Some(current_key));
+        }
+
+        found
+    }
+
+    /// Rotate the node holding data, lifting its right child for `Left` or its left
+    /// child for `Right` into its place
+    ///
+    /// A rotation never changes the in order sequence, so any series of these keeps
+    /// the tree a valid BST and every key stays valid. Nothing is changed if data is
+    /// not in the tree or the node has no child on the side to lift
+    pub fn rotate_at(&mut self, data: &T, dir: TreeDirection) -> Result<(), RotateError> {
+        let (node_key, parent) = self.find_with_parent(data).ok_or(RotateError::NotFound)?;
+
+        if self.storage.get(node_key).child(dir.opposite()).is_none() {
+            return Err(RotateError::MissingChild);
+        }
+
+        // The subtree holds the same nodes afterwards, so no ancestor's size changes
+        let lifted = self.rotate(node_key, dir);
+        self.link(parent, Some(lifted));
+
+        Ok(())
+    }
+
+    /// Descend towards data, returning the node it is in or the last node passed if it is absent
+    ///
+    /// The ordering is of the data against the returned node's data
+    fn search_end(&self, data: &T) -> Option<(SlotKey, Ordering)> {
+        let mut current_key = self.root?;
+
+        loop {
+            let current_node = self.storage.get(current_key);
+
+            let ordering = self.settings.compare(data, &current_node.data);
+            let next_key = match ordering {
+                Ordering::Equal => None,
+                Ordering::Less => current_node.left,
+                Ordering::Greater => current_node.right,
+            };
+
+            match next_key {
+                Some(next_key) => current_key = next_key,
+                None => return Some((current_key, ordering)),
+            }
+        }
+    }
+
+    /// Get the smallest value strictly greater than data, whether or not data is in the tree
+    ///
+    /// When data is in the tree this is the next value in order, found in its right
+    /// subtree or else at the nearest ancestor it sits to the left of. So starting at
+    /// `min` and calling this repeatedly visits every value in ascending order
+    pub fn successor(&self, data: &T) -> Option<&T> {
+        let (end_key, ordering) = self.search_end(data)?;
+
+        // Searching left past a node means it is the smallest value above data
+        let successor = match ordering {
+            Ordering::Less => Some(end_key),
+            _ => self.next_key(end_key),
+        };
+
+        successor.map(|node_key| &self.storage.get(node_key).data)
+    }
+
+    /// Get the largest value strictly less than data, whether or not data is in the tree
+    ///
+    /// This mirrors `successor`, so stepping down from `max` visits every value in
+    /// descending order
+    pub fn predecessor(&self, data: &T) -> Option<&T> {
+        let (end_key, ordering) = self.search_end(data)?;
+
+        // Searching right past a node means it is the largest value below data
+        let predecessor = match ordering {
+            Ordering::Greater => Some(end_key),
+            _ => self.prev_key(end_key),
+        };
+
+        predecessor.map(|node_key| &self.storage.get(node_key).data)
+    }
+
+    /// Iterate over the values in order, starting at the first one not less than `start`
+    ///
+    /// `start` does not have to be in the tree. The iterator's stack is seeded with
+    /// the nodes on the path down to that value that are still to come, so starting
+    /// is O(height) rather than skipping past every smaller value
+    pub fn iter_from(&self, start: &T) -> InOrderIter<'_, T> {
+        let mut iter = InOrderIter {
+            storage: &self.storage,
+            stack: Vec::new(),
+        };
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            let current_node = self.storage.get(current_key);
+
+            match self.settings.search_step(start, &current_node.data) {
+                // This node comes after start, as does everything on its right
+                Some(TreeDirection::Left) => {
+                    iter.stack.push(current_key);
+                    current = current_node.left;
+                }
+                // This node and its left side come before start
+                Some(TreeDirection::Right) => current = current_node.right,
+                None => {
+                    iter.stack.push(current_key);
+                    break;
+                }
+            }
+        }
+
+        iter
+    }
+
+    /// Get the value closest to target, measured by `distance`
+    ///
+    /// The search walks down once, keeping the closest value on each side of
+    /// target as it goes, then picks whichever of the two is nearer. A tie goes
+    /// to the smaller value, and an empty tree gives `None`
+    pub fn nearest_by<D, F>(&self, target: &T, mut distance: F) -> Option<&T>
+    where
+        D: PartialOrd,
+        F: FnMut(&T, &T) -> D,
+    {
+        let mut floor: Option<&T> = None;
+        let mut ceiling: Option<&T> = None;
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            let current_node = self.storage.get(current_key);
+
+            current = match self.settings.compare(target, &current_node.data) {
+                Ordering::Equal => return Some(&current_node.data),
+                Ordering::Less => {
+                    ceiling = Some(&current_node.data);
+                    current_node.left
+                }
+                Ordering::Greater => {
+                    floor = Some(&current_node.data);
+                    current_node.right
+                }
+            };
+        }
+
+        match (floor, ceiling) {
+            (Some(floor), Some(ceiling)) => {
+                if distance(ceiling, target) < distance(floor, target) {
+                    Some(ceiling)
+                } else {
+                    Some(floor)
+                }
+            }
+            (floor, None) => floor,
+            (None, ceiling) => ceiling,
+        }
+    }
+
+    /// Get the value closest to target by absolute difference
+    ///
+    /// The larger value is always subtracted from the smaller, so unsigned types
+    /// cannot underflow. A tie goes to the smaller value
+    pub fn nearest(&self, target: &T) -> Option<&T>
+    where
+        T: Clone + core::ops::Sub<Output = T>,
+    {
+        self.nearest_by(target, |value, target| {
+            if value > target {
+                value.clone() - target.clone()
+            } else {
+                target.clone() - value.clone()
+            }
+        })
+    }
+
+    /// Overwrite the data of the node a key points at, returning the old data
+    ///
+    /// This is for updating a payload that does not affect ordering, without the
+    /// cost of a remove and insert
+    ///
+    /// # Panics
+    ///
+    /// If the key does not point at a node, or in debug builds if the new data does
+    /// not sit strictly between the values before and after it
+    pub fn replace(&mut self, key: SlotKey, data: T) -> T {
+        let less = |a: &T, b: &T| self.settings.compare(a, b) == Ordering::Less;
+        debug_assert!(
+            self.prev_key(key)
+                .is_none_or(|prev_key| less(&self.storage.get(prev_key).data, &data)),
+            "Replacement must stay greater than the value before it"
+        );
+        debug_assert!(
+            self.next_key(key)
+                .is_none_or(|next_key| less(&data, &self.storage.get(next_key).data)),
+            "Replacement must stay less than the value after it"
+        );
+
+        core::mem::replace(&mut self.storage.get_mut(key).data, data)
+    }
+
+    /// Exchange the values of two nodes, but only if both still fit between their neighbours
+    ///
+    /// Each value must still order after the one before its new place and before the
+    /// one after, so this only succeeds for values that order alike, such as equal
+    /// keys with different payloads under a custom comparator. Otherwise nothing
+    /// changes. Swapping a node with itself always succeeds
+    pub fn swap_values(&mut self, a: SlotKey, b: SlotKey) -> Result<(), SwapError> {
+        for key in [a, b] {
+            if self.node(key).is_none() {
+                return Err(SwapError::MissingKey(key));
+            }
+        }
+        if a == b {
+            return Ok(());
+        }
+
+        // The value that would end up at a key once swapped
+        let incoming = |key: SlotKey| {
+            let from = match key {
+                key if key == a => b,
+                key if key == b => a,
+                key => key,
+            };
+            &self.storage.get(from).data
+        };
+        // A search for the later value would pass to the right of the earlier one
+        let ordered = |earlier: &T, later: &T| {
+            self.settings.search_step(later, earlier) == Some(TreeDirection::Right)
+        };
+        let fits = |key: SlotKey| {
+            let data = incoming(key);
+
+            self.prev_key(key)
+                .is_none_or(|prev_key| ordered(incoming(prev_key), data))
+                && self
+                    .next_key(key)
+                    .is_none_or(|next_key| ordered(data, incoming(next_key)))
+        };
+        if !fits(a) || !fits(b) {
+            return Err(SwapError::OutOfOrder);
+        }
+
+        let [a_node, b_node] = self
+            .storage
+            .get_disjoint_mut([a, b])
+            .expect("Both keys point at nodes and are different");
+        core::mem::swap(&mut a_node.data, &mut b_node.data);
+
+        Ok(())
+    }
+
+    /// Check every parent link agrees with the child links
+    ///
+    /// The root must have no parent, and every other node's parent must point back at it
+    pub fn check_parents(&self) -> Result<(), String> {
+        if let Some(root) = self.root
+            && let Some(parent) = self.storage.get(root).parent
+        {
+            return Err(format!("Root {:?} has parent {:?}", root, parent));
+        }
+
+        for (index, slot) in self.storage.slots.iter().enumerate() {
+            let Some(node) = &slot.item else {
+                continue;
+            };
+
+            let node_key = SlotKey::new(index);
+            if Some(node_key) == self.root {
+                continue;
+            }
+
+            let Some(parent_key) = node.parent else {
+                return Err(format!(
+                    "Node {:?} is not the root but has no parent",
+                    node_key
+                ));
+            };
+
+            let Some(parent) = self.storage.try_get(parent_key) else {
+                return Err(format!(
+                    "Node {:?} has parent {:?}, which is not a node",
+                    node_key, parent_key
+                ));
+            };
+            if parent.left != Some(node_key) && parent.right != Some(node_key) {
+                return Err(format!(
+                    "Node {:?} has parent {:?}, which does not have it as a child",
+                    node_key, parent_key
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check every value is greater than the ones in its left subtree and less than the
+    /// ones in its right subtree
+    ///
+    /// The values are walked in order, so the first pair found out of order is reported
+    pub fn check_order(&self) -> Result<(), String> {
+        let mut previous: Option<&T> = None;
+
+        for data in self.iter_in_order() {
+            if let Some(previous) = previous
+                && self.settings.search_step(data, previous) != Some(TreeDirection::Right)
+            {
+                return Err(format!(
+                    "{:?} comes after {:?} in order but is not greater",
+                    data, previous
+                ));
+            }
+
+            previous = Some(data);
+        }
+
+        Ok(())
+    }
+
+    /// Check no value is stored twice, returning `false` if one is
+    ///
+    /// Every duplicate policy keeps a single copy, so the in order values must be
+    /// strictly increasing. This is the same walk as `check_order`, for callers that
+    /// only want a yes or no
+    pub fn assert_no_duplicates(&self) -> bool {
+        self.check_order().is_ok()
+    }
+
+    /// Check the tree is a valid BST whose links all agree, returning `false` if not
+    ///
+    /// This is `check_order` and `check_parents` together, for callers that reshape the
+    /// tree by hand, such as with `rotate_at`, and only want a yes or no
+    pub fn is_valid_bst(&self) -> bool {
+        self.check_order().is_ok() && self.check_parents().is_ok()
+    }
+
+    /// Count the values strictly less than data, whether or not data is in the tree
+    ///
+    /// This uses the subtree sizes, so only a single path is walked
+    pub fn rank(&self, data: &T) -> usize {
+        self.count_below(data, false)
+    }
+
+    /// Count the values less than data, also counting data itself if `inclusive`
+    fn count_below(&self, data: &T, inclusive: bool) -> usize {
+        let mut count = 0;
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            let current_node = self.storage.get(current_key);
+
+            let below = match self.settings.compare(data, &current_node.data) {
+                Ordering::Less => false,
+                Ordering::Equal => inclusive,
+                Ordering::Greater => true,
+            };
+
+            if below {
+                // This node and everything left of it counts
+                count += subtree_size(&self.storage, current_node.left) + 1;
+                current = current_node.right;
+            } else {
+                current = current_node.left;
+            }
+        }
+
+        count
+    }
+
+    /// Get the `k`th smallest value within `[low, high]`, counting from zero
+    ///
+    /// Two rank walks find where the range starts and ends, and a third walk down the
+    /// subtree sizes finds the value, so this is O(height) however large the range or
+    /// `k`. Returns `None` if the range holds `k` values or fewer, or `low > high`
+    pub fn nth_in_range(&self, low: &T, high: &T, k: usize) -> Option<&T> {
+        let start = self.count_below(low, false);
+        let end = self.count_below(high, true);
+        let index = start.checked_add(k).filter(|&index| index < end)?;
+
+        self.nth_key(index)
+            .map(|node_key| &self.storage.get(node_key).data)
+    }
+
+    /// Count the values within `[low, high]` without visiting them
+    ///
+    /// This is two rank walks, so O(height). If `low > high` the range is empty
+    pub fn count_range(&self, low: &T, high: &T) -> usize {
+        self.count_below(high, true)
+            .saturating_sub(self.count_below(low, false))
+    }
+
+    /// Count the values that start with `prefix`, such as the keys a partly typed word
+    /// could complete to
+    ///
+    /// Values with a prefix sit together in order, so this is two rank walks, to the
+    /// first value not below the prefix and to the first past every value starting
+    /// with it, and O(height). No upper bound string is built, so a prefix ending in
+    /// `char::MAX` needs no special case. An empty prefix counts every value
+    ///
+    /// # NOTE
+    ///
+    /// This relies on the tree ordering values the way their `str`s order, as it does
+    /// for `String`
+    pub fn count_prefix(&self, prefix: &str) -> usize
+    where
+        T: core::borrow::Borrow<str>,
+    {
+        let before = self.count_leading(|data| data.borrow() < prefix);
+        let through = self.count_leading(|data| {
+            let data: &str = data.borrow();
+            data < prefix || data.starts_with(prefix)
+        });
+
+        through - before
+    }
+
+    /// Count the values `below` holds for, given it holds for every value up to some
+    /// point in order and for none after
+    fn count_leading<F>(&self, below: F) -> usize
+    where
+        F: Fn(&T) -> bool,
+    {
+        let mut count = 0;
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            let current_node = self.storage.get(current_key);
+
+            if below(&current_node.data) {
+                // This node and everything left of it counts
+                count += subtree_size(&self.storage, current_node.left) + 1;
+                current = current_node.right;
+            } else {
+                current = current_node.left;
+            }
+        }
+
+        count
+    }
+
+    /// Remove every value outside `[low, high]`, keeping the rest in place
+    ///
+    /// Each bound is handled by a single walk down one side of the tree. A node past
+    /// the bound is dropped along with its outer subtree, and its inner subtree takes
+    /// its place, so this is O(height + removed) and the surviving nodes keep their
+    /// keys. This beats two `split`s, which move one side into new storage. If
+    /// `low > high` the tree is emptied
+    pub fn retain_range(&mut self, low: &T, high: &T) {
+        if self.settings.compare(low, high) == Ordering::Greater {
+            self.clear();
+            return;
+        }
+
+        self.trim_beyond(low, TreeDirection::Left);
+        self.trim_beyond(high, TreeDirection::Right);
+        self.compact_if_sparse();
+    }
+
+    /// Remove every value strictly less than `bound`, returning how many were removed
+    ///
+    /// This is the lower half of `retain_range`, so it is O(height + removed) and the
+    /// surviving nodes keep their keys
+    pub fn remove_less_than(&mut self, bound: &T) -> usize {
+        let old_len = self.len();
+        self.trim_beyond(bound, TreeDirection::Left);
+        self.compact_if_sparse();
+
+        old_len - self.len()
+    }
+
+    /// Remove every value strictly greater than `bound`, returning how many were removed
+    ///
+    /// This is the upper half of `retain_range`, so it is O(height + removed) and the
+    /// surviving nodes keep their keys
+    pub fn remove_greater_than(&mut self, bound: &T) -> usize {
+        let old_len = self.len();
+        self.trim_beyond(bound, TreeDirection::Right);
+        self.compact_if_sparse();
+
+        old_len - self.len()
+    }
+
+    /// Remove every value past a bound on one side, below it for `Left` and above it for `Right`
+    fn trim_beyond(&mut self, bound: &T, side: TreeDirection) {
+        let mut hook = None;
+        let mut current = self.root;
+
+        // The nodes kept on the walk, whose subtrees may have shrunk
+        let mut path = Vec::new();
+
+        while let Some(current_key) = current {
+            let current_node = self.storage.get(current_key);
+
+            let beyond = match side {
+                TreeDirection::Left => {
+                    self.settings.compare(&current_node.data, bound) == Ordering::Less
+                }
+                TreeDirection::Right => {
+                    self.settings.compare(&current_node.data, bound) == Ordering::Greater
+                }
+            };
+
+            if beyond {
+                // Everything on the outer side is further past the bound, so only
+                // the inner side can hold values to keep
+                let (outer, inner) = (
+                    current_node.child(side),
+                    current_node.child(side.opposite()),
+                );
+
+                self.free_subtree(outer);
+                self.storage.remove(current_key);
+                self.link(hook, inner);
+
+                current = inner;
+            } else {
+                path.push(current_key);
+                hook = Some((current_key, side));
+                current = current_node.child(side);
+            }
+        }
+
+        // Deepest first, so each node's children are up to date before it
+        for node_key in path.into_iter().rev() {
+            self.update_size(node_key);
+        }
+    }
+
+    /// Check if any value lies within `[low, high]`, without collecting the range
+    ///
+    /// A node below `low` rules out its left side and one above `high` rules out
+    /// its right side, so this is a single O(height) descent. If `low > high` the
+    /// range is empty
+    pub fn any_in_range(&self, low: &T, high: &T) -> bool {
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            let current_node = self.storage.get(current_key);
+
+            current = if self.settings.compare(&current_node.data, low) == Ordering::Less {
+                current_node.right
+            } else if self.settings.compare(&current_node.data, high) == Ordering::Greater {
+                current_node.left
+            } else {
+                return true;
+            };
+        }
+
+        false
+    }
+
+    /// Fold the values within `[low, high]` in ascending order, without collecting them
+    ///
+    /// Subtrees wholly below `low` are skipped on the way down, and the walk stops at
+    /// the first value above `high`, so this is O(height + k) for k values in range.
+    /// If `low > high` the range is empty and `init` is returned
+    pub fn fold_range<B, F>(&self, low: &T, high: &T, init: B, mut f: F) -> B
+    where
+        F: FnMut(B, &T) -> B,
+    {
+        let mut accumulator = init;
+
+        // Nodes in range of `low` whose right side is still to visit
+        let mut stack = Vec::new();
+        let mut current = self.root;
+
+        loop {
+            while let Some(current_key) = current {
+                let current_node = self.storage.get(current_key);
+
+                if self.settings.compare(&current_node.data, low) == Ordering::Less {
+                    // This node and its left side are all below the range
+                    current = current_node.right;
+                } else {
+                    stack.push(current_key);
+                    current = current_node.left;
+                }
+            }
+
+            let Some(node_key) = stack.pop() else {
+                break;
+            };
+            let node = self.storage.get(node_key);
+
+            // Values come in order, so everything after this is above the range too
+            if self.settings.compare(&node.data, high) == Ordering::Greater {
+                break;
+            }
+
+            accumulator = f(accumulator, &node.data);
+            current = node.right;
+        }
+
+        accumulator
+    }
+
+    /// Deletes an element if it exists
+    ///
+    /// The node is unlinked in place, its in order successor taking its place if it
+    /// has two children, as `pop_root` does. So no other node moves, the keys of the
+    /// other values stay valid, and the maximum height cannot be hit as the tree
+    /// never gets deeper
+    pub fn delete(mut self, data: &T) -> Self {
+        if let Some((node_key, _)) = self.find_with_parent(data) {
+            self.remove_key(node_key);
+            self.compact_if_sparse();
+        }
+
+        self
+    }
+
+    /// Print the tree an order provided
+    #[cfg(feature = "std")]
+    pub fn out_order(&self, ordering: TreeOrdering) {
+        // LNR
+
+        println!("-- {:?} order start: ", ordering);
+
+        self.inner_out(ordering);
+
+        println!("-- {:?} order end", ordering);
+    }
+
+    /// Print every node in the order provided
+    #[cfg(feature = "std")]
+    fn inner_out(&self, ordering: TreeOrdering) {
+        self.for_each(ordering, |data| println!("{:?}", data));
+    }
+
+    /// Combine every value into an accumulator, visiting them in the order provided
+    pub fn fold<B, F>(&self, init: B, ordering: TreeOrdering, mut f: F) -> B
+    where
+        F: FnMut(B, &T) -> B,
+    {
+        // The accumulator is moved in and out of f, so it needs a temporary home
+        let mut acc = Some(init);
+
+        self.visit_keys(ordering, |node_key| {
+            let current = acc.take().expect("Accumulator is always put back");
+            acc = Some(f(current, &self.storage.get(node_key).data));
+        });
+
+        acc.expect("Accumulator is always put back")
+    }
+
+    /// Call `f` with every value in the order provided
+    pub fn for_each<F>(&self, ordering: TreeOrdering, mut f: F)
+    where
+        F: FnMut(&T),
+    {
+        self.visit_keys(ordering, |node_key| f(&self.storage.get(node_key).data));
+    }
+
+    /// Count the values that `f` returns true for
+    ///
+    /// Every value is visited, as nothing is known about how `f` relates to the
+    /// ordering. For a bound such as `x < k`, `rank` answers in O(height) instead
+    pub fn count_if<F>(&self, f: F) -> usize
+    where
+        F: Fn(&T) -> bool,
+    {
+        self.iter_pre_order().filter(|data| f(data)).count()
+    }
+
+    /// Add up every value in order, giving the type's zero for an empty tree
+    ///
+    /// This uses `Sum`, so the empty case and overflow behave exactly as they do
+    /// for `Iterator::sum`
+    pub fn sum<'a>(&'a self) -> T
+    where
+        T: core::iter::Sum<&'a T>,
+    {
+        self.iter_in_order().sum()
+    }
+
+    /// Multiply every value in order, giving the type's one for an empty tree
+    ///
+    /// This uses `Product`, so the empty case and overflow behave exactly as they
+    /// do for `Iterator::product`
+    pub fn product<'a>(&'a self) -> T
+    where
+        T: core::iter::Product<&'a T>,
+    {
+        self.iter_in_order().product()
+    }
+
+    /// Call `f` with every value in no particular order, spreading the work across threads
+    ///
+    /// The two subtrees below a node are handed to `rayon::join`, down to a fixed depth
+    /// and while they are big enough to be worth a task, after which each subtree is
+    /// walked on its own thread with an explicit stack. Without the `rayon` feature
+    /// this just walks the whole tree on the current thread
+    pub fn par_for_each<F>(&self, f: F)
+    where
+        F: Fn(&T) + Sync,
+        T: Sync,
+    {
+        #[cfg(feature = "rayon")]
+        self.par_visit(self.root, 0, &f);
+
+        #[cfg(not(feature = "rayon"))]
+        self.walk_subtree(self.root, &f);
+    }
+
+    /// Visit a subtree for `par_for_each`, splitting it into parallel tasks while it is shallow and large
+    #[cfg(feature = "rayon")]
+    fn par_visit<F>(&self, subtree_root: Option<SlotKey>, depth: usize, f: &F)
+    where
+        F: Fn(&T) + Sync,
+        T: Sync,
+    {
+        // Splitting stops at a fixed depth, so this recursion cannot overflow on a degenerate tree
+        const MAX_SPLIT_DEPTH: usize = 12;
+        const MIN_SPLIT_SIZE: usize = 1024;
+
+        let Some(node_key) = subtree_root else {
+            return;
+        };
+        let node = self.storage.get(node_key);
+
+        if depth >= MAX_SPLIT_DEPTH || node.size < MIN_SPLIT_SIZE {
+            self.walk_subtree(subtree_root, f);
+            return;
+        }
+
+        f(&node.data);
+        rayon::join(
+            || self.par_visit(node.left, depth + 1, f),
+            || self.par_visit(node.right, depth + 1, f),
+        );
+    }
+
+    /// Call `f` with every value in a subtree, in preorder on the current thread
+    fn walk_subtree<F>(&self, subtree_root: Option<SlotKey>, f: &F)
+    where
+        F: Fn(&T),
+    {
+        let mut stack: Vec<SlotKey> = subtree_root.into_iter().collect();
+
+        while let Some(node_key) = stack.pop() {
+            let node = self.storage.get(node_key);
+
+            f(&node.data);
+
+            stack.extend(node.right);
+            stack.extend(node.left);
+        }
+    }
+
+    /// Call `f` with mutable access to every value, in no particular order
+    ///
+    /// # WARNING
+    ///
+    /// `f` must not change how a value orders relative to the others. The tree is
+    /// not rearranged afterwards, so doing so breaks the BST invariant and later
+    /// searches, inserts and deletes will silently give wrong results
+    pub fn for_each_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut T),
+    {
+        let mut stack: Vec<SlotKey> = self.root.into_iter().collect();
+
+        while let Some(node_key) = stack.pop() {
+            let node = self.storage.get_mut(node_key);
+
+            f(&mut node.data);
+
+            if let Some(right) = node.right {
+                stack.push(right);
+            }
+            if let Some(left) = node.left {
+                stack.push(left);
+            }
+        }
+    }
+
+    /// Call `f` with mutable access to every value after its children, along with the
+    /// values of those children
+    ///
+    /// As the children are always done first, `f` sees their values already updated,
+    /// so this can work out something bottom up, such as a total stored in each node.
+    /// The walk keeps an explicit stack, so deep trees are fine
+    ///
+    /// # WARNING
+    ///
+    /// As with `for_each_mut`, `f` must not change how a value orders relative to the
+    /// others
+    pub fn for_each_post_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&mut T, Option<&T>, Option<&T>),
+    {
+        // Each entry is a node and whether its children have already been pushed
+        let mut stack: Vec<(SlotKey, bool)> =
+            self.root.map(|root| (root, false)).into_iter().collect();
+
+        while let Some((node_key, expanded)) = stack.pop() {
+            let node = self.storage.get(node_key);
+            let (left, right) = (node.left, node.right);
+
+            if !expanded {
+                stack.push((node_key, true));
+                stack.extend(right.map(|right| (right, false)));
+                stack.extend(left.map(|left| (left, false)));
+                continue;
+            }
+
+            let disjoint = "A node and its children are different live slots";
+            match (left, right) {
+                (None, None) => f(&mut self.storage.get_mut(node_key).data, None, None),
+                (Some(left), None) => {
+                    let [node, left] = self
+                        .storage
+                        .get_disjoint_mut([node_key, left])
+                        .expect(disjoint);
+                    f(&mut node.data, Some(&left.data), None);
+                }
+                (None, Some(right)) => {
+                    let [node, right] = self
+                        .storage
+                        .get_disjoint_mut([node_key, right])
+                        .expect(disjoint);
+                    f(&mut node.data, None, Some(&right.data));
+                }
+                (Some(left), Some(right)) => {
+                    let [node, left, right] = self
+                        .storage
+                        .get_disjoint_mut([node_key, left, right])
+                        .expect(disjoint);
+                    f(&mut node.data, Some(&left.data), Some(&right.data));
+                }
+            }
+        }
+    }
+
+    /// Call `f` with the storage and the key of every node in order, using Morris traversal
+    ///
+    /// Rather than keeping a stack, each node's in order predecessor has its empty
+    /// right child pointed back at the node, and the thread is removed again on the
+    /// second visit. This only needs O(1) extra space, and the tree is left exactly
+    /// as it was once the traversal finishes. Only `right` is ever rewired, so
+    /// parent pointers and sizes are never touched
+    fn morris_visit_keys<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&SlotMap<TreeNode<T>>, SlotKey),
+    {
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            let Some(left) = self.storage.get(current_key).left else {
+                // Nothing smaller is left, so this node is due
+                f(&self.storage, current_key);
+                current = self.storage.get(current_key).right;
+                continue;
+            };
+
+            // Find the in order predecessor, stopping at a thread back to this node
+            let mut predecessor = left;
+            while let Some(right) = self.storage.get(predecessor).right
+                && right != current_key
+            {
+                predecessor = right;
+            }
+
+            if self.storage.get(predecessor).right.is_none() {
+                // First visit, so thread the predecessor back here and go left
+                self.storage.get_mut(predecessor).right = Some(current_key);
+                current = Some(left);
+            } else {
+                // Second visit, the left subtree is done so remove the thread
+                self.storage.get_mut(predecessor).right = None;
+
+                f(&self.storage, current_key);
+                current = self.storage.get(current_key).right;
+            }
+        }
+    }
+
+    /// Call `f` with every value in order, using O(1) extra space
+    ///
+    /// `&mut self` is needed as the tree is temporarily threaded while walking it
+    ///
+    /// # WARNING
+    ///
+    /// If `f` panics partway through, some threads are left in place and the tree
+    /// is corrupted
+    pub fn morris_for_each<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T),
+    {
+        self.morris_visit_keys(|storage, node_key| f(&storage.get(node_key).data));
+    }
+
+    /// Collect references to every value in order, walking the tree with Morris traversal
+    ///
+    /// The walk itself uses O(1) extra space, with only the returned `Vec` growing.
+    /// `&mut self` is needed as the tree is temporarily threaded while walking it
+    pub fn iter_in_order_morris(&mut self) -> Vec<&T> {
+        let mut keys = Vec::with_capacity(self.storage.item_count);
+        self.morris_visit_keys(|_, node_key| keys.push(node_key));
+
+        keys.into_iter()
+            .map(|node_key| &self.storage.get(node_key).data)
+            .collect()
+    }
+
+    /// Collect references to every value like `to_vec`, or `None` if the links are corrupt
+    ///
+    /// This checks `has_cycle` first, so it gives up rather than looping forever
+    pub fn try_to_vec(&self, ordering: TreeOrdering) -> Option<Vec<&T>> {
+        (!self.has_cycle()).then(|| self.to_vec(ordering))
+    }
+
+    /// Collect references to every value in the order provided
+    pub fn to_vec(&self, ordering: TreeOrdering) -> Vec<&T> {
+        let mut out = Vec::new();
+
+        self.visit_keys(ordering, |node_key| {
+            out.push(&self.storage.get(node_key).data);
+        });
+
+        out
+    }
+
+    /// Write the values in order as a JSON array of strings, e.g. `["A","C","D"]`
+    ///
+    /// Every value is quoted using its `Display` form, even numbers, leaving the
+    /// reader to decide how to interpret them
+    pub fn to_json_array(&self) -> String
+    where
+        T: Display,
+    {
+        let mut out = String::from("[");
+
+        self.visit_keys(TreeOrdering::In, |node_key| {
+            if out.len() > 1 {
+                out.push(',');
+            }
+            json::write_string(&mut out, &self.storage.get(node_key).data.to_string());
+        });
+
+        out.push(']');
+        out
+    }
+
+    /// Build a balanced tree from a JSON array, parsing each element with `FromStr`
+    ///
+    /// Elements may be strings or bare values such as numbers, and may come in any
+    /// order, with repeats dropped. Gives `None` if the text is not a flat array or
+    /// any element fails to parse
+    pub fn from_json_array(text: &str) -> Option<Self>
+    where
+        T: core::str::FromStr,
+    {
+        let values = json::parse_array(text)?
+            .into_iter()
+            .map(|element| element.parse().ok())
+            .collect::<Option<Vec<T>>>()?;
+
+        Some(Tree::from_unsorted(values))
+    }
+
+    /// Render the tree as an indented diagram, one node per line
+    ///
+    /// Each child is labelled `L` or `R`, and a node with only one child shows
+    /// the missing side as `(none)` so the shape is unambiguous
+    /// An empty tree is drawn as an empty string
+    pub fn to_ascii(&self) -> String {
+        let Some(root) = self.root else {
+            return String::new();
+        };
+
+        let mut out = format!("{:?}\n", self.storage.get(root).data);
+
+        // Each entry is a child slot (which may be empty), the prefix drawn before it,
+        // whether it is the last child of its parent and which side it is on
+        let mut stack: Vec<(Option<SlotKey>, String, bool, &str)> = Vec::new();
+        self.push_ascii_children(&mut stack, root, String::new());
+
+        while let Some((child, prefix, is_last, side)) = stack.pop() {
+            let connector = if is_last { "└── " } else { "├── " };
+
+            let Some(child_key) = child else {
+                out.push_str(&format!("{}{}{}: (none)\n", prefix, connector, side));
+                continue;
+            };
+
+            let node = self.storage.get(child_key);
+            out.push_str(&format!(
+                "{}{}{}: {:?}\n",
+                prefix, connector, side, node.data
+            ));
+
+            let child_prefix = if is_last {
+                format!("{}    ", prefix)
+            } else {
+                format!("{}│   ", prefix)
+            };
+
+            self.push_ascii_children(&mut stack, child_key, child_prefix);
+        }
+
+        out
+    }
+
+    /// Queue the children of a node for `to_ascii`, leaves have nothing queued
+    fn push_ascii_children(
+        &self,
+        stack: &mut Vec<(Option<SlotKey>, String, bool, &str)>,
+        node_key: SlotKey,
+        prefix: String,
+    ) {
+        let node = self.storage.get(node_key);
+
+        if node.left.is_none() && node.right.is_none() {
+            return;
+        }
+
+        // Right is pushed first so the left child is drawn first
+        stack.push((node.right, prefix.clone(), true, "R"));
+        stack.push((node.left, prefix, false, "L"));
+    }
+
+    /// Write the tree in Newick notation, e.g. `((1,)2,3)4;` for a node 4 whose left
+    /// child 2 has only a left child
+    ///
+    /// The children of a node come in brackets before its label, always as a left and
+    /// right pair, so a missing child is left blank and a leaf has no brackets at all.
+    /// Labels use the `Display` form, quoted with `'` if they hold characters Newick
+    /// treats specially. An empty tree is written as just `;`
+    pub fn to_newick(&self) -> String
+    where
+        T: Display,
+    {
+        /// What is still to be written, the top being next
+        enum Part {
+            Subtree(Option<SlotKey>),
+            Label(SlotKey),
+            Text(&'static str),
+        }
+
+        let mut out = String::new();
+        let mut stack = vec![Part::Subtree(self.root)];
+
+        while let Some(part) = stack.pop() {
+            match part {
+                Part::Subtree(None) => {}
+                Part::Subtree(Some(node_key)) => {
+                    let node = self.storage.get(node_key);
+                    stack.push(Part::Label(node_key));
+
+                    if node.left.is_some() || node.right.is_some() {
+                        // Pushed backwards so they come off in writing order
+                        stack.push(Part::Text(")"));
+                        stack.push(Part::Subtree(node.right));
+                        stack.push(Part::Text(","));
+                        stack.push(Part::Subtree(node.left));
+                        stack.push(Part::Text("("));
+                    }
+                }
+                Part::Label(node_key) => {
+                    write_newick_label(&mut out, &self.storage.get(node_key).data.to_string());
+                }
+                Part::Text(text) => out.push_str(text),
+            }
+        }
+
+        out.push(';');
+        out
+    }
+
+    /// Calculate the height of every subtree, indexed by the slot of its root
+    ///
+    /// Heights count nodes, so a leaf has height 1 and a missing child height 0
+    fn subtree_heights(&self) -> Vec<usize> {
+        let mut heights = vec![0; self.storage.capacity()];
+
+        // Post order guarantees both children are done before their parent
+        self.visit_keys(TreeOrdering::Post, |node_key| {
+            let node = self.storage.get(node_key);
+
+            let left = node.left.map_or(0, |left| heights[left.index]);
+            let right = node.right.map_or(0, |right| heights[right.index]);
+
+            heights[node_key.index] = 1 + left.max(right);
+        });
+
+        heights
+    }
+
+    /// Check if every node has either no children or both children
+    pub fn is_full(&self) -> bool {
+        let mut full = true;
+
+        self.visit_keys(TreeOrdering::Pre, |node_key| {
+            let node = self.storage.get(node_key);
+
+            if node.left.is_some() != node.right.is_some() {
+                full = false;
+            }
+        });
+
+        full
+    }
+
+    /// Check if every internal node has both children and all leaves share a depth
+    pub fn is_perfect(&self) -> bool {
+        if !self.is_full() {
+            return false;
+        }
+
+        let mut leaf_depth = None;
+        let mut perfect = true;
+
+        self.visit_breadth(|node_key, depth| {
+            let node = self.storage.get(node_key);
+
+            if node.left.is_none() && *leaf_depth.get_or_insert(depth) != depth {
+                perfect = false;
+            }
+        });
+
+        perfect
+    }
+
+    /// Check if every level is full except possibly the last, which is filled from the left
+    pub fn is_complete(&self) -> bool {
+        let mut queue = VecDeque::<Option<SlotKey>>::new();
+        queue.push_back(self.root);
+
+        // Once a gap is found, every position after it must also be empty
+        let mut seen_gap = false;
+
+        while let Some(entry) = queue.pop_front() {
+            let Some(node_key) = entry else {
+                seen_gap = true;
+                continue;
+            };
+
+            if seen_gap {
+                return false;
+            }
+
+            let node = self.storage.get(node_key);
+            queue.push_back(node.left);
+            queue.push_back(node.right);
+        }
+
+        true
+    }
+
+    /// Check if the heights of the two subtrees of every node differ by at most one
+    pub fn is_balanced(&self) -> bool {
+        let heights = self.subtree_heights();
+        let mut balanced = true;
+
+        self.visit_keys(TreeOrdering::Pre, |node_key| {
+            let node = self.storage.get(node_key);
+
+            let left = node.left.map_or(0, |left| heights[left.index]);
+            let right = node.right.map_or(0, |right| heights[right.index]);
+
+            if left.abs_diff(right) > 1 {
+                balanced = false;
+            }
+        });
+
+        balanced
+    }
+
+    /// Get the height of the left subtree minus the height of the right one, for
+    /// the node holding data
+    ///
+    /// Positive means the node leans left. Any node with a factor outside -1..=1
+    /// is one an AVL tree would rotate. Gives `None` if data is absent
+    pub fn balance_factor(&self, data: &T) -> Option<i64> {
+        let (node_key, _) = self.find_with_parent(data)?;
+        let node = self.storage.get(node_key);
+
+        let left = self.subtree_height(node.left) as i64;
+        let right = self.subtree_height(node.right) as i64;
+
+        Some(left - right)
+    }
+
+    /// Get the number of nodes on the longest path down from a node, zero for a missing one
+    fn subtree_height(&self, subtree_root: Option<SlotKey>) -> usize {
+        let mut height = 0;
+        let mut stack: Vec<(SlotKey, usize)> =
+            subtree_root.map(|root| (root, 1)).into_iter().collect();
+
+        while let Some((node_key, depth)) = stack.pop() {
+            let node = self.storage.get(node_key);
+            height = height.max(depth);
+
+            stack.extend(node.left.map(|left| (left, depth + 1)));
+            stack.extend(node.right.map(|right| (right, depth + 1)));
+        }
+
+        height
+    }
+
+    /// Get the number of edges on the longest path between any two nodes
+    ///
+    /// The path does not have to pass through the root
+    pub fn diameter(&self) -> usize {
+        let mut heights = vec![0; self.storage.capacity()];
+        let mut diameter = 0;
+
+        // Post order guarantees both children are done before their parent
+        self.visit_keys(TreeOrdering::Post, |node_key| {
+            let node = self.storage.get(node_key);
+
+            let left = node.left.map_or(0, |left| heights[left.index]);
+            let right = node.right.map_or(0, |right| heights[right.index]);
+
+            // The longest path bending at this node goes down both sides,
+            // and each subtree height is the number of edges from this node into it
+            diameter = diameter.max(left + right);
+
+            heights[node_key.index] = 1 + left.max(right);
+        });
+
+        diameter
+    }
+
+    /// Print the tree, breadth first
+    #[cfg(feature = "std")]
+    pub fn out_breadth(&self) {
+        println!("-- Breadth order start: ");
+
+        for (_, data) in self.iter_breadth() {
+            println!("{:?}", data);
+        }
+
+        println!("-- Breadth order end ");
+    }
+
+    /// Call `f` with the key and depth of every node, breadth first
+    fn visit_breadth<F>(&self, mut f: F)
+    where
+        F: FnMut(SlotKey, usize),
+    {
+        let mut queue = VecDeque::<(SlotKey, usize)>::new();
+        queue.extend(self.root.map(|root| (root, 0)));
+
+        while let Some((current_key, depth)) = queue.pop_front() {
+            let current_node = self.storage.get(current_key);
+
+            f(current_key, depth);
+
+            // Insert the left node if it exists
+            if let Some(left_node) = current_node.left {
+                queue.push_back((left_node, depth + 1));
+            }
+
+            // Insert the right node if it exists
+            if let Some(right_node) = current_node.right {
+                queue.push_back((right_node, depth + 1));
+            }
+        }
+    }
+
+    /// Walk the tree breadth first, yielding each value with its depth, where the root is at depth 0
+    ///
+    /// Values on the same level come out left to right, and the queue is only
+    /// advanced as the iterator is consumed
+    pub fn iter_breadth(&self) -> impl Iterator<Item = (usize, &T)> {
+        let mut queue = VecDeque::<(SlotKey, usize)>::new();
+        queue.extend(self.root.map(|root| (root, 0)));
+
+        core::iter::from_fn(move || {
+            let (current_key, depth) = queue.pop_front()?;
+            let current_node = self.storage.get(current_key);
+
+            // Insert the left node if it exists
+            if let Some(left_node) = current_node.left {
+                queue.push_back((left_node, depth + 1));
+            }
+
+            // Insert the right node if it exists
+            if let Some(right_node) = current_node.right {
+                queue.push_back((right_node, depth + 1));
+            }
+
+            Some((depth, &current_node.data))
+        })
+    }
+
+    /// Walk the tree breadth first like `iter_breadth`, but only down to `max_depth`
+    ///
+    /// Children of nodes at `max_depth` are never queued, so only the top of the
+    /// tree is visited. A `max_depth` of 0 yields just the root
+    pub fn iter_breadth_limited(&self, max_depth: usize) -> impl Iterator<Item = (usize, &T)> {
+        let mut queue = VecDeque::<(SlotKey, usize)>::new();
+        queue.extend(self.root.map(|root| (root, 0)));
+
+        core::iter::from_fn(move || {
+            let (current_key, depth) = queue.pop_front()?;
+            let current_node = self.storage.get(current_key);
+
+            if depth < max_depth {
+                queue.extend(current_node.left.map(|left_node| (left_node, depth + 1)));
+                queue.extend(current_node.right.map(|right_node| (right_node, depth + 1)));
+            }
+
+            Some((depth, &current_node.data))
+        })
+    }
+
+    /// Consume the tree, moving every value out breadth first
+    ///
+    /// Each node is taken out of the storage as it is reached, so the values are
+    /// moved rather than cloned and dropping the iterator part way drops the rest
+    pub fn into_breadth(mut self) -> impl Iterator<Item = T> {
+        let mut queue: VecDeque<SlotKey> = self.root.take().into_iter().collect();
+
+        core::iter::from_fn(move || {
+            let node = self.storage.remove(queue.pop_front()?);
+
+            queue.extend(node.left);
+            queue.extend(node.right);
+
+            Some(node.data)
+        })
+    }
+
+    /// Collect every value breadth first into `out`, using `queue` as the work queue
+    ///
+    /// Both buffers are cleared first and keep their capacity, so traversing again
+    /// and again with the same buffers stops allocating once they are big enough
+    pub fn breadth_into<'a>(&'a self, queue: &mut VecDeque<SlotKey>, out: &mut Vec<&'a T>) {
+        queue.clear();
+        out.clear();
+
+        queue.extend(self.root);
+
+        while let Some(current_key) = queue.pop_front() {
+            let current_node = self.storage.get(current_key);
+
+            out.push(&current_node.data);
+
+            queue.extend(current_node.left);
+            queue.extend(current_node.right);
+        }
+    }
+
+    /// Get the first value on each level from the left, which is what a left side view shows
+    ///
+    /// Unlike `left_spine` this can jump across subtrees, when a right child sits on
+    /// a level that no left child reaches
+    pub fn left_view(&self) -> Vec<&T> {
+        let mut out = Vec::new();
+
+        self.visit_breadth(|node_key, depth| {
+            // The first node seen at each depth is the leftmost
+            if depth == out.len() {
+                out.push(&self.storage.get(node_key).data);
+            }
+        });
+
+        out
+    }
+
+    /// Get the last value on each level from the left, which is what a right side view shows
+    pub fn right_view(&self) -> Vec<&T> {
+        let mut out = Vec::new();
+
+        self.visit_breadth(|node_key, depth| {
+            // Later nodes at the same depth are further right, so each replaces the last
+            let data = &self.storage.get(node_key).data;
+            if depth == out.len() {
+                out.push(data);
+            } else {
+                out[depth] = data;
+            }
+        });
+
+        out
+    }
+
+    /// Get every path from the root down to a leaf, leftmost leaf first
+    ///
+    /// Each path starts with the root's value and ends with the leaf's value
+    pub fn root_to_leaf_paths(&self) -> Vec<Vec<&T>> {
+        let mut paths = Vec::new();
+        let Some(root_key) = self.root else {
+            return paths;
+        };
+
+        // Each entry carries the path down to, but not including, its node
+        let mut stack = vec![(root_key, Vec::new())];
+        while let Some((node_key, mut path)) = stack.pop() {
+            let node = self.storage.get(node_key);
+            path.push(&node.data);
+
+            match (node.left, node.right) {
+                (None, None) => paths.push(path),
+                (Some(left_key), None) => stack.push((left_key, path)),
+                (None, Some(right_key)) => stack.push((right_key, path)),
+                (Some(left_key), Some(right_key)) => {
+                    // Pushed last so the left side is popped, and so collected, first
+                    stack.push((right_key, path.clone()));
+                    stack.push((left_key, path));
+                }
+            }
+        }
+
+        paths
+    }
+
+    /// Get the largest sum of the values along any path from the root down to a leaf
+    ///
+    /// Returns `None` for an empty tree
+    ///
+    /// # NOTE
+    ///
+    /// Overflow behaves exactly as `+` does for `T`
+    pub fn max_path_sum(&self) -> Option<T>
+    where
+        T: core::ops::Add<Output = T> + Copy + Ord,
+    {
+        let root_key = self.root?;
+        let mut best = None;
+
+        // Each entry carries the sum of the path down to and including its node
+        let mut stack = vec![(root_key, self.storage.get(root_key).data)];
+        while let Some((node_key, sum)) = stack.pop() {
+            let node = self.storage.get(node_key);
+            if node.left.is_none() && node.right.is_none() {
+                best = best.max(Some(sum));
+                continue;
+            }
+
+            for child_key in [node.left, node.right].into_iter().flatten() {
+                stack.push((child_key, sum + self.storage.get(child_key).data));
+            }
+        }
+
+        best
+    }
+
+    /// Count the nodes at each depth, where index 0 is the root's depth
+    ///
+    /// This is one breadth first pass, and the length is the height of the tree, so
+    /// an empty tree gives an empty Vec. A balanced tree doubles at every level until
+    /// the last, while a degenerate one has a single node at each
+    pub fn depth_histogram(&self) -> Vec<usize> {
+        let mut histogram = Vec::new();
+
+        self.visit_breadth(|_, depth| {
+            // Breadth first never skips a level, so each new depth is one past the end
+            if depth == histogram.len() {
+                histogram.push(0);
+            }
+            histogram[depth] += 1;
+        });
+
+        histogram
+    }
+
+    /// Get every value at a depth from left to right, where the root is at depth 0
+    ///
+    /// A depth beyond the bottom of the tree gives an empty Vec
+    pub fn nodes_at_depth(&self, depth: usize) -> Vec<&T> {
+        let mut out = Vec::new();
+
+        self.visit_breadth(|node_key, node_depth| {
+            if node_depth == depth {
+                out.push(&self.storage.get(node_key).data);
+            }
+        });
+
+        out
+    }
+
+    /// Count the nodes on each level, starting from the root
+    pub fn level_widths(&self) -> Vec<usize> {
+        let mut widths = Vec::new();
+
+        self.visit_breadth(|_, depth| {
+            // Breadth first reaches each depth in turn, so at most one level is added
+            if depth == widths.len() {
+                widths.push(0);
+            }
+
+            widths[depth] += 1;
+        });
+
+        widths
+    }
+
+    /// Get the values on each level from left to right, starting from the root
+    ///
+    /// An empty tree gives an empty Vec
+    pub fn levels(&self) -> Vec<Vec<&T>> {
+        let mut levels: Vec<Vec<&T>> = Vec::new();
+
+        self.visit_breadth(|node_key, depth| {
+            // Breadth first reaches each depth in turn, so at most one level is added
+            if depth == levels.len() {
+                levels.push(Vec::new());
+            }
+
+            levels[depth].push(&self.storage.get(node_key).data);
+        });
+
+        levels
+    }
+
+    /// Get a value on the bottom level along with its depth, where the root is at depth 0
+    ///
+    /// This is one breadth first walk keeping the last node seen, so of the values on
+    /// the bottom level the rightmost is given. An empty tree gives `None`
+    pub fn deepest(&self) -> Option<(&T, usize)> {
+        let mut deepest = None;
+
+        self.visit_breadth(|node_key, depth| {
+            deepest = Some((&self.storage.get(node_key).data, depth));
+        });
+
+        deepest
+    }
+
+    /// Measure the shape of the tree in a single walk
+    ///
+    /// This is one O(n) pass rather than one per measurement. An empty tree gives
+    /// zero for every count and is balanced
+    pub fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats {
+            node_count: 0,
+            height: 0,
+            min_depth: 0,
+            leaf_count: 0,
+            internal_count: 0,
+            balanced: true,
+        };
+        let mut min_depth = None;
+        let mut heights = vec![0_usize; self.storage.capacity()];
+
+        // Each entry is a node, its depth and whether its children have been pushed
+        let mut stack: Vec<(SlotKey, usize, bool)> =
+            self.root.map(|root| (root, 0, false)).into_iter().collect();
+
+        while let Some((node_key, depth, expanded)) = stack.pop() {
+            let node = self.storage.get(node_key);
+
+            if !expanded {
+                // Both children are finished before the node is popped again
+                stack.push((node_key, depth, true));
+                stack.extend(node.right.map(|right| (right, depth + 1, false)));
+                stack.extend(node.left.map(|left| (left, depth + 1, false)));
+                continue;
+            }
+
+            let left = node.left.map_or(0, |left| heights[left.index]);
+            let right = node.right.map_or(0, |right| heights[right.index]);
+            heights[node_key.index] = 1 + left.max(right);
+
+            stats.node_count += 1;
+            stats.height = stats.height.max(depth + 1);
+            stats.balanced &= left.abs_diff(right) <= 1;
+
+            if node.left.is_none() && node.right.is_none() {
+                stats.leaf_count += 1;
+                min_depth = Some(min_depth.map_or(depth, |min: usize| min.min(depth)));
+            } else {
+                stats.internal_count += 1;
+            }
+        }
+
+        stats.min_depth = min_depth.unwrap_or(0);
+        stats
+    }
+
+    /// Find the depth of the shallowest leaf, where the root is at depth 0
+    ///
+    /// In a balanced tree this is within one of the deepest leaf. An empty tree gives 0
+    pub fn min_depth(&self) -> usize {
+        let mut min_depth = None;
+
+        self.visit_breadth(|node_key, depth| {
+            let node = self.storage.get(node_key);
+
+            // Breadth first reaches the shallowest leaf first
+            if min_depth.is_none() && node.left.is_none() && node.right.is_none() {
+                min_depth = Some(depth);
+            }
+        });
+
+        min_depth.unwrap_or(0)
+    }
+
+    /// Find the mean depth of every leaf, where the root is at depth 0
+    ///
+    /// An empty tree gives 0
+    pub fn avg_leaf_depth(&self) -> f64 {
+        let (mut leaves, mut total_depth) = (0_usize, 0_usize);
+
+        self.visit_breadth(|node_key, depth| {
+            let node = self.storage.get(node_key);
+
+            if node.left.is_none() && node.right.is_none() {
+                leaves += 1;
+                total_depth += depth;
+            }
+        });
+
+        if leaves == 0 {
+            0.0
+        } else {
+            total_depth as f64 / leaves as f64
+        }
+    }
+}
+
+impl<T> Tree<T>
+where
+    T: PartialOrd + Debug,
+{
+    /// Insert data into a tree of values that are only partially ordered, such as floats
+    ///
+    /// The descent uses `partial_cmp`, and if data cannot be compared with a value on
+    /// the way down it is handed back as `InsertError::Incomparable`, leaving the tree
+    /// unchanged. Data that cannot be compared with itself, such as NaN, is refused
+    /// the same way even for an empty tree. Duplicates and the maximum length and
+    /// height are handled as in `insert_ordered`
+    ///
+    /// # NOTE
+    ///
+    /// A comparator given to `new_with` is not used here
+    pub fn insert_partial(&mut self, data: T) -> Result<(), InsertError<T>> {
+        // Otherwise NaN could become the root, and then nothing else could be inserted
+        if data.partial_cmp(&data) != Some(Ordering::Equal) {
+            return Err(InsertError::Incomparable(data));
+        }
+
+        let mut parent = None;
+        let mut current = self.root;
+        let mut depth = 0;
+
+        while let Some(current_key) = current {
+            let current_node = self.storage.get(current_key);
+            depth += 1;
+
+            let direction = match data.partial_cmp(&current_node.data) {
+                Some(Ordering::Less) => TreeDirection::Left,
+                Some(Ordering::Greater) => TreeDirection::Right,
+                Some(Ordering::Equal) => return self.insert_duplicate(current_key, data).map(drop),
+                None => return Err(InsertError::Incomparable(data)),
+            };
+
+            parent = Some((current_key, direction));
+            current = current_node.child(direction);
+        }
+
+        // Duplicates never add a node, so the limit only matters once we know this is new
+        if self.at_max_len() {
+            return Err(InsertError::Full(data));
+        }
+        if self.too_deep(depth) {
+            return Err(InsertError::TooDeep(data));
+        }
+
+        let new_key = self.storage.insert(TreeNode::new(data));
+        self.link(parent, Some(new_key));
+        self.update_sizes_upward(parent.map(|(parent_key, _)| parent_key));
+
+        Ok(())
+    }
+
+    /// Check if a tree of partially ordered values contains data
+    ///
+    /// Data that cannot be compared with a value on the way down is never in the
+    /// tree, as `insert_partial` would have refused it, so this gives `false`
+    pub fn contains_partial(&self, data: &T) -> bool {
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            let current_node = self.storage.get(current_key);
+
+            current = match data.partial_cmp(&current_node.data) {
+                Some(Ordering::Less) => current_node.left,
+                Some(Ordering::Greater) => current_node.right,
+                Some(Ordering::Equal) => return true,
+                None => return false,
+            };
+        }
+
+        false
+    }
+}
+
+impl<T> Tree<T>
+where
+    T: Eq + Ord + Debug + ByteCodec,
+{
+    /// Encode the tree into a compact byte buffer
+    ///
+    /// The layout is a version byte, the number of values as a little endian `u64`,
+    /// then every value in ascending order. Only the values are stored, so
+    /// `from_bytes` gives back a balanced tree rather than the original shape
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![BYTES_FORMAT_VERSION];
+        self.len().encode(&mut out);
+
+        self.visit_keys(TreeOrdering::In, |node_key| {
+            self.storage.get(node_key).data.encode(&mut out);
+        });
+
+        out
+    }
+
+    /// Decode a buffer written by `to_bytes` into a balanced tree
+    ///
+    /// Gives `None` if the version is unknown, the buffer is truncated or has
+    /// trailing bytes, or the values are not strictly ascending
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (&version, mut rest) = bytes.split_first()?;
+        if version != BYTES_FORMAT_VERSION {
+            return None;
+        }
+
+        let (count, used) = usize::decode(rest)?;
+        rest = &rest[used..];
+
+        // The count is untrusted, so each value taking at least a byte caps the allocation
+        let mut values: Vec<T> = Vec::with_capacity(count.min(rest.len()));
+        for _ in 0..count {
+            let (value, used) = T::decode(rest)?;
+            rest = &rest[used..];
+
+            if values.last().is_some_and(|last| *last >= value) {
+                return None;
+            }
+            values.push(value);
+        }
+
+        if !rest.is_empty() {
+            return None;
+        }
+
+        Some(Tree::from_sorted(values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check the links, cached sizes and ordering of a tree all agree
+    fn assert_valid<T>(tree: &Tree<T>)
+    where
+        T: Eq + Ord + Debug,
+    {
+        tree.check_parents().unwrap();
+        tree.check_order().unwrap();
+
+        let mut count = 0;
+        tree.visit_keys(TreeOrdering::Post, |node_key| {
+            let node = tree.storage.get(node_key);
+            let size = 1
+                + subtree_size(&tree.storage, node.left)
+                + subtree_size(&tree.storage, node.right);
+            assert_eq!(node.size, size, "Cached size of {:?} is wrong", node.data);
+            count += 1;
+        });
+        assert_eq!(count, tree.len());
+    }
+
+    fn tree_of(values: &[i32]) -> Tree<i32> {
+        let mut tree = Tree::empty();
+        for &value in values {
+            tree.insert_ordered(value).unwrap();
+        }
+
+        tree
+    }
+
+    fn in_order(tree: &Tree<i32>) -> Vec<i32> {
+        tree.iter_in_order().copied().collect()
+    }
+
+    #[test]
+    fn delete_respects_max_height() {
+        let mut tree = Tree::empty().with_max_height(3);
+        for value in [4, 2, 6, 1, 3, 5, 7] {
+            tree.insert_ordered(value).unwrap();
+        }
+
+        let tree = tree.delete(&4);
+
+        assert_valid(&tree);
+        assert_eq!(in_order(&tree), [1, 2, 3, 5, 6, 7]);
+        assert_eq!(tree.max_height(), Some(3));
+    }
+
+    #[test]
+    fn delete_keeps_other_keys() {
+        let tree = tree_of(&[4, 2, 6, 1, 3, 5, 7]);
+        let key = tree.max_key().unwrap();
+
+        let tree = tree.delete(&4).delete(&10);
+
+        assert_valid(&tree);
+        assert_eq!(tree.get_by_key(key), Some(&7));
+        assert_eq!(in_order(&tree), [1, 2, 3, 5, 6, 7]);
+    }
+
+    /// A small seeded generator, so failures can be replayed
+    fn next(state: &mut u64) -> u64 {
+        *state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        *state >> 33
+    }
+
+    #[test]
+    fn rotate_at_keeps_the_tree_a_bst() {
+        let values: Vec<i32> = (0..64).map(|value| (value * 37) % 64).collect();
+        let mut tree = tree_of(&values);
+        let expected = in_order(&tree);
+        let mut state = 1;
+
+        for _ in 0..2000 {
+            let value = (next(&mut state) % 70) as i32;
+            let dir = if next(&mut state).is_multiple_of(2) {
+                TreeDirection::Left
+            } else {
+                TreeDirection::Right
+            };
+
+            match tree.rotate_at(&value, dir) {
+                Ok(()) | Err(RotateError::MissingChild) => assert!(value < 64),
+                Err(RotateError::NotFound) => assert!(value >= 64),
+            }
+
+            assert!(tree.is_valid_bst());
+            assert_valid(&tree);
+            assert_eq!(in_order(&tree), expected);
+        }
+    }
+
+    #[test]
+    fn rotate_at_reports_why_it_did_nothing() {
+        let mut tree = tree_of(&[2, 1]);
+
+        assert_eq!(
+            tree.rotate_at(&2, TreeDirection::Left),
+            Err(RotateError::MissingChild)
+        );
+        assert_eq!(
+            tree.rotate_at(&3, TreeDirection::Right),
+            Err(RotateError::NotFound)
+        );
+        assert_eq!(tree.to_level_order(), [Some(&2), Some(&1)]);
+
+        tree.rotate_at(&2, TreeDirection::Right).unwrap();
+        assert_valid(&tree);
+        assert_eq!(tree.to_level_order(), [Some(&1), None, Some(&2)]);
+    }
+
+    #[test]
+    fn from_preorder_round_trips_the_shape() {
+        let shapes: [&[i32]; 5] = [
+            &[],
+            &[1],
+            &[5, 3, 8, 1, 4, 7, 9, 2, 6],
+            &[1, 2, 3, 4, 5, 6],
+            &[6, 5, 4, 3, 2, 1],
+        ];
+
+        for values in shapes {
+            let tree = tree_of(values);
+            let preorder: Vec<i32> = tree
+                .to_vec(TreeOrdering::Pre)
+                .into_iter()
+                .copied()
+                .collect();
+
+            let rebuilt = Tree::from_preorder(preorder.clone());
+
+            assert_valid(&rebuilt);
+            assert_eq!(rebuilt.shape_signature(), tree.shape_signature());
+            assert!(rebuilt.to_vec(TreeOrdering::Pre).into_iter().eq(&preorder));
+        }
+    }
+
+    /// Every node's key with its child and parent keys, in pre order
+    fn links(tree: &Tree<i32>) -> Vec<[Option<SlotKey>; 4]> {
+        let mut links = Vec::new();
+        tree.visit_keys(TreeOrdering::Pre, |node_key| {
+            let node = tree.storage.get(node_key);
+            links.push([Some(node_key), node.left, node.right, node.parent]);
+        });
+
+        links
+    }
+
+    #[test]
+    fn morris_traversal_leaves_the_tree_unchanged() {
+        let skewed: Vec<i32> = (0..50).collect();
+        let balanced = Tree::from_sorted((0..50).collect());
+
+        for mut tree in [tree_of(&skewed), balanced, Tree::empty()] {
+            let shape = tree.shape_signature();
+            let before = links(&tree);
+
+            let mut seen = Vec::new();
+            tree.morris_for_each(|value| seen.push(*value));
+            assert_eq!(seen, in_order(&tree));
+            assert_eq!(tree.iter_in_order_morris().len(), tree.len());
+
+            assert_eq!(tree.shape_signature(), shape);
+            assert_eq!(links(&tree), before);
+            assert_valid(&tree);
+        }
+    }
+
+    #[test]
+    fn pop_root_until_empty() {
+        // Both subtrees of the root are several levels deep, and zig-zag on the way down
+        let mut tree = tree_of(&[
+            50, 20, 80, 10, 35, 65, 95, 5, 15, 30, 40, 60, 70, 90, 99, 32, 38, 62, 68, 33, 61,
+        ]);
+        let mut remaining = in_order(&tree);
+
+        while let Some(root_key) = tree.root {
+            let root_value = *tree.get_by_key(root_key).unwrap();
+
+            assert_eq!(tree.pop_root(), Some(root_value));
+            remaining.retain(|&value| value != root_value);
+
+            assert_valid(&tree);
+            assert_eq!(tree.len(), remaining.len());
+            assert_eq!(in_order(&tree), remaining);
+        }
+
+        assert!(remaining.is_empty());
+        assert_eq!(tree.pop_root(), None);
+    }
+
+    #[test]
+    fn successor_steps_through_every_value() {
+        let values: Vec<i32> = (0..50).map(|value| (value * 17) % 50).collect();
+        let tree = tree_of(&values);
+
+        let mut visited = Vec::new();
+        let mut current = tree.min().copied();
+        while let Some(value) = current {
+            visited.push(value);
+            current = tree.successor(&value).copied();
+        }
+        assert_eq!(visited, in_order(&tree));
+
+        let mut visited = Vec::new();
+        let mut current = tree.max().copied();
+        while let Some(value) = current {
+            visited.push(value);
+            current = tree.predecessor(&value).copied();
+        }
+        visited.reverse();
+        assert_eq!(visited, in_order(&tree));
+    }
+
+    #[test]
+    fn successor_climbs_to_an_ancestor() {
+        //       4
+        //     2   6
+        //    1 3 5 7
+        let tree = tree_of(&[4, 2, 6, 1, 3, 5, 7]);
+
+        // 3 has no right child, so its successor is the root two levels up
+        assert_eq!(tree.successor(&3), Some(&4));
+        assert_eq!(tree.predecessor(&5), Some(&4));
+        assert_eq!(tree.successor(&1), Some(&2));
+        assert_eq!(tree.successor(&7), None);
+        assert_eq!(tree.predecessor(&1), None);
+
+        // Values missing from the tree still have neighbours
+        assert_eq!(tree.successor(&0), Some(&1));
+        assert_eq!(tree.successor(&10), None);
+        assert_eq!(Tree::<i32>::empty().successor(&0), None);
+
+        let (key, _) = tree.find_with_parent(&3).unwrap();
+        let next = tree.successor_key(key).unwrap();
+        assert_eq!(tree.get_by_key(next), Some(&4));
+    }
+
+    /// Walk a cursor to `value`, remove it, and check where the cursor ends up
+    fn remove_with_cursor(value: i32, expected_next: Option<i32>) {
+        //       4
+        //     2   6
+        //    1 3 5 7
+        //           8
+        let values = [4, 2, 6, 1, 3, 5, 7, 8];
+        let mut tree = tree_of(&values);
+        let keys: Vec<(SlotKey, i32)> = tree.iter_with_keys().map(|(key, &v)| (key, v)).collect();
+
+        let mut cursor = tree.cursor_min_mut();
+        while cursor.current() != Some(&value) {
+            cursor.move_next();
+        }
+        assert_eq!(cursor.remove_current(), Some(value));
+        assert_eq!(cursor.current().copied(), expected_next);
+        if expected_next.is_none() {
+            assert_eq!(cursor.remove_current(), None);
+        }
+
+        assert_valid(&tree);
+        let mut expected: Vec<i32> = values.into_iter().filter(|&v| v != value).collect();
+        expected.sort();
+        assert_eq!(in_order(&tree), expected);
+        for (key, v) in keys.into_iter().filter(|&(_, v)| v != value) {
+            assert_eq!(tree.get_by_key(key), Some(&v));
+        }
+    }
+
+    #[test]
+    fn cursor_removes_a_leaf() {
+        remove_with_cursor(1, Some(2));
+    }
+
+    #[test]
+    fn cursor_removes_a_node_with_one_child() {
+        remove_with_cursor(7, Some(8));
+    }
+
+    #[test]
+    fn cursor_removes_a_node_with_two_children() {
+        remove_with_cursor(6, Some(7));
+    }
+
+    #[test]
+    fn cursor_removes_the_max_and_is_exhausted() {
+        remove_with_cursor(8, None);
+    }
+
+    #[test]
+    fn cursor_removes_the_root() {
+        remove_with_cursor(4, Some(5));
+    }
+
+    #[test]
+    fn cursor_removes_everything() {
+        let mut tree = tree_of(&[4, 2, 6, 1, 3, 5, 7, 8]);
+
+        let mut cursor = tree.cursor_min_mut();
+        let mut removed = Vec::new();
+        while let Some(value) = cursor.remove_current() {
+            removed.push(value);
+        }
+
+        assert_eq!(removed, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn sum_and_product_of_integers() {
+        let tree = tree_of(&[4, 2, 6, 1, 3, 5]);
+        assert_eq!(tree.sum(), 21);
+        assert_eq!(tree.product(), 720);
+
+        let tree = tree_of(&[-3, 7, 0]);
+        assert_eq!(tree.sum(), 4);
+        assert_eq!(tree.product(), 0);
+
+        let mut tree = Tree::empty();
+        tree.insert_ordered(10u64).unwrap();
+        assert_eq!(tree.sum(), 10);
+        assert_eq!(tree.product(), 10);
+    }
+
+    #[test]
+    fn sum_and_product_of_an_empty_tree_are_the_identities() {
+        let tree = Tree::<i32>::empty();
+        assert_eq!(tree.sum(), 0);
+        assert_eq!(tree.product(), 1);
+
+        let tree = Tree::<u8>::empty();
+        assert_eq!(tree.sum(), 0);
+        assert_eq!(tree.product(), 1);
+    }
+
+    #[test]
+    fn clone_into_copies_the_settings() {
+        let mut src = Tree::empty()
+            .with_max_height(4)
+            .with_duplicate_policy(DuplicatePolicy::Replace);
+        for value in [4, 2, 6, 1, 3] {
+            src.insert_ordered(value).unwrap();
+        }
+
+        let mut dst = tree_of(&[10, 20, 30, 40, 50, 60, 70]);
+        assert_eq!(dst.sorted_cache().len(), 7);
+        src.clone_into(&mut dst);
+
+        assert_valid(&dst);
+        assert_eq!(in_order(&dst), [1, 2, 3, 4, 6]);
+        assert_eq!(dst.max_height(), Some(4));
+        assert_eq!(dst.duplicate_policy(), DuplicatePolicy::Replace);
+        assert_eq!(dst.root_key(), src.root_key());
+        for (key, value) in src.iter_with_keys() {
+            assert_eq!(dst.get_by_key(key), Some(value));
+        }
+
+        let cached: Vec<SlotKey> = dst.sorted_cache().to_vec();
+        let expected: Vec<SlotKey> = src.iter_with_keys().map(|(key, _)| key).collect();
+        assert_eq!(cached, expected);
+    }
+
+    #[test]
+    fn insert_partial_rejects_nan() {
+        let mut tree = Tree::empty();
+        assert!(matches!(
+            tree.insert_partial(f64::NAN),
+            Err(InsertError::Incomparable(value)) if value.is_nan()
+        ));
+        assert!(tree.is_empty());
+
+        for value in [2.0, 1.0, 3.0] {
+            tree.insert_partial(value).unwrap();
+        }
+        assert!(matches!(
+            tree.insert_partial(f64::NAN),
+            Err(InsertError::Incomparable(_))
+        ));
+        assert_eq!(tree.len(), 3);
+        assert!(tree.contains_partial(&1.0));
+        assert!(!tree.contains_partial(&f64::NAN));
+    }
+
+    /// A tree ordering values from largest to smallest with `new_with`
+    fn reversed_of(values: &[i32]) -> Tree<i32> {
+        let mut tree = Tree::new_with(|a: &i32, b: &i32| b.cmp(a), |a, b| a == b);
+        for &value in values {
+            tree.insert_ordered(value).unwrap();
+        }
+
+        tree
+    }
+
+    #[test]
+    fn custom_comparator_orders_searches_and_ranks() {
+        let tree = reversed_of(&[5, 2, 8, 1, 3, 7, 9, 4, 6]);
+        assert_valid(&tree);
+        assert_eq!(in_order(&tree), [9, 8, 7, 6, 5, 4, 3, 2, 1]);
+
+        assert_eq!(tree.successor(&5), Some(&4));
+        assert_eq!(tree.predecessor(&5), Some(&6));
+        assert_eq!(tree.successor(&10), Some(&9));
+        assert_eq!(tree.rank(&5), 4);
+        assert_eq!(tree.count_range(&7, &3), 5);
+        assert_eq!(tree.nth_in_range(&7, &3, 1), Some(&6));
+        assert!(tree.any_in_range(&7, &3));
+        assert!(!tree.any_in_range(&3, &7));
+        assert_eq!(
+            tree.fold_range(&7, &3, Vec::new(), |mut seen, &value| {
+                seen.push(value);
+                seen
+            }),
+            [7, 6, 5, 4, 3]
+        );
+        assert_eq!(tree.nearest_by(&0, |a, b| a.abs_diff(*b)), Some(&1));
+    }
+
+    #[test]
+    fn custom_comparator_orders_reshaping() {
+        let (high, low) = reversed_of(&[5, 2, 8, 1, 3, 7, 9, 4, 6]).split(&5);
+        assert_valid(&high);
+        assert_valid(&low);
+        assert_eq!(in_order(&high), [9, 8, 7, 6]);
+        assert_eq!(in_order(&low), [5, 4, 3, 2, 1]);
+
+        let mut tree = reversed_of(&[5, 2, 8, 1, 3, 7, 9, 4, 6]);
+        assert!(tree.splay(&3));
+        assert_valid(&tree);
+        assert_eq!(tree.get_by_key(tree.root_key().unwrap()), Some(&3));
+
+        assert_eq!(tree.remove_less_than(&7), 2);
+        assert_eq!(tree.remove_greater_than(&3), 2);
+        assert_valid(&tree);
+        assert_eq!(in_order(&tree), [7, 6, 5, 4, 3]);
+
+        tree.retain_range(&6, &4);
+        assert_valid(&tree);
+        assert_eq!(in_order(&tree), [6, 5, 4]);
+
+        let merged = reversed_of(&[9, 8]).merge(reversed_of(&[2, 1])).unwrap();
+        assert_valid(&merged);
+        assert_eq!(in_order(&merged), [9, 8, 2, 1]);
+        assert!(reversed_of(&[2, 1]).merge(reversed_of(&[9, 8])).is_err());
+    }
+
+    #[test]
+    fn custom_comparator_orders_merge_join() {
+        let left = reversed_of(&[1, 3, 5]);
+        let right = reversed_of(&[3, 4, 5, 6]);
+
+        let joined: Vec<MergeSide<'_, i32>> = left.merge_join(&right).collect();
+        assert_eq!(
+            joined,
+            [
+                MergeSide::Right(&6),
+                MergeSide::Both(&5, &5),
+                MergeSide::Right(&4),
+                MergeSide::Both(&3, &3),
+                MergeSide::Left(&1),
+            ]
+        );
+
+        assert!(reversed_of(&[3, 5]).is_subset(&right));
+        assert!(!left.is_subset(&right));
+    }
+
+    #[test]
+    fn check_parents_reports_a_parent_in_an_empty_slot() {
+        let mut tree = tree_of(&[2, 1, 3, 4]);
+        let (spare, _) = tree.find_with_parent(&4).unwrap();
+        tree.remove_key(spare);
+        tree.check_parents().unwrap();
+
+        // Point a child at the freed slot, as a corrupt tree might
+        let (child, _) = tree.find_with_parent(&1).unwrap();
+        tree.storage.get_mut(child).parent = Some(spare);
+
+        let error = tree.check_parents().unwrap_err();
+        assert!(error.contains("which is not a node"), "{}", error);
+        assert!(!tree.is_valid_bst());
+    }
+}
@@ -0,0 +1,327 @@
+use alloc::vec::Vec;
+use core::{cmp::Ordering, fmt::Debug};
+
+use crate::{
+    rotation::{self, BinaryNode},
+    slotmap::{SlotKey, SlotMap},
+    tree::TreeDirection,
+};
+
+/// A side is too heavy once its weight is more than `DELTA` times the other side's
+///
+/// This is the same as saying neither side may hold more than 3/4 of a node's weight,
+/// i.e. alpha = 1/4
+const DELTA: usize = 3;
+
+/// When rebalancing, a single rotation is used if the heavy child's inner side weighs
+/// less than `GAMMA` times its outer side, otherwise a double rotation
+const GAMMA: usize = 2;
+
+struct WbNode<T> {
+    data: T,
+    /// Number of nodes in the subtree rooted here, including this one
+    size: usize,
+    left: Option<SlotKey>,
+    right: Option<SlotKey>,
+}
+
+impl<T> Debug for WbNode<T>
+where
+    T: Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("WbNode")
+            .field("data", &self.data)
+            .field("size", &self.size)
+            .field("left", &self.left)
+            .field("right", &self.right)
+            .finish()
+    }
+}
+
+impl<T> WbNode<T> {
+    fn new(data: T) -> Self {
+        Self {
+            data,
+            size: 1,
+            left: None,
+            right: None,
+        }
+    }
+}
+
+impl<T> BinaryNode for WbNode<T> {
+    type Data = T;
+
+    fn data(&self) -> &T {
+        &self.data
+    }
+
+    fn child(&self, direction: TreeDirection) -> Option<SlotKey> {
+        match direction {
+            TreeDirection::Left => self.left,
+            TreeDirection::Right => self.right,
+        }
+    }
+
+    fn set_child(&mut self, direction: TreeDirection, child: Option<SlotKey>) {
+        match direction {
+            TreeDirection::Left => self.left = child,
+            TreeDirection::Right => self.right = child,
+        }
+    }
+}
+
+/// A BST that balances itself by keeping the sizes of its subtrees in proportion
+///
+/// Every node stores the size of its subtree, and the weight of a subtree is its size
+/// plus one. After each insert or remove, any node on the path where one side weighs
+/// more than three times the other (alpha = 1/4, so neither side holds more than 3/4
+/// of the weight) is fixed with a single or double rotation
+///
+/// This bounds the height to about log_{4/3}(n), roughly 2.4 * log2(n), in the worst
+/// case rather than just in expectation. The sizes also answer `rank` and `nth` in
+/// O(log n)
+pub struct WbTree<T> {
+    storage: SlotMap<WbNode<T>>,
+    /// `None` when the tree is empty
+    root: Option<SlotKey>,
+}
+
+impl<T> Debug for WbTree<T>
+where
+    T: Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("WbTree")
+            .field("storage", &self.storage)
+            .field("root", &self.root)
+            .finish()
+    }
+}
+
+impl<T> Default for WbTree<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> WbTree<T> {
+    /// Create an empty weight balanced tree
+    pub fn new() -> Self {
+        Self {
+            storage: SlotMap::new(),
+            root: None,
+        }
+    }
+
+    /// Count the values in the tree
+    pub fn len(&self) -> usize {
+        self.storage.item_count
+    }
+
+    /// Check if the tree has no values
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Get the number of nodes in a subtree, which is zero for a missing one
+    fn size(&self, subtree_root: Option<SlotKey>) -> usize {
+        subtree_root.map_or(0, |key| self.storage.get(key).size)
+    }
+
+    /// Get the weight of a subtree, its size plus one
+    fn weight(&self, subtree_root: Option<SlotKey>) -> usize {
+        self.size(subtree_root) + 1
+    }
+
+    /// Recalculate the size of a node from its children
+    fn update_size(&mut self, node_key: SlotKey) {
+        let node = self.storage.get(node_key);
+        let size = 1 + self.size(node.left) + self.size(node.right);
+
+        self.storage.get_mut(node_key).size = size;
+    }
+
+    /// Rotate the subtree rooted at `key`, keeping the sizes of the two nodes that moved correct
+    fn rotate(&mut self, key: SlotKey, direction: TreeDirection) -> SlotKey {
+        let lifted = rotation::rotate(&mut self.storage, key, direction);
+
+        // The old root is now below the lifted node, so it has to be done first
+        self.update_size(key);
+        self.update_size(lifted);
+
+        lifted
+    }
+
+    /// Point a parent's child, or the root if there is no parent, at a node
+    fn link(&mut self, parent: Option<(SlotKey, TreeDirection)>, child: Option<SlotKey>) {
+        rotation::link(&mut self.storage, &mut self.root, parent, child);
+    }
+
+    /// Restore the weight balance of a node whose children are each balanced,
+    /// returning the key of whichever node now roots the subtree
+    ///
+    /// The caller must link the returned key into the place `key` used to be
+    fn rebalance(&mut self, key: SlotKey) -> SlotKey {
+        self.update_size(key);
+
+        let node = self.storage.get(key);
+        let left_weight = self.weight(node.left);
+        let right_weight = self.weight(node.right);
+
+        let heavy = if right_weight > DELTA * left_weight {
+            TreeDirection::Right
+        } else if left_weight > DELTA * right_weight {
+            TreeDirection::Left
+        } else {
+            return key;
+        };
+
+        let heavy_key = node
+            .child(heavy)
+            .expect("The heavy side weighs more than one, so it has a node");
+        let heavy_node = self.storage.get(heavy_key);
+        let inner_weight = self.weight(heavy_node.child(heavy.opposite()));
+        let outer_weight = self.weight(heavy_node.child(heavy));
+
+        if inner_weight >= GAMMA * outer_weight {
+            // The weight is on the inside, so it needs lifting out to the heavy side first
+            let lifted = self.rotate(heavy_key, heavy);
+            self.storage.get_mut(key).set_child(heavy, Some(lifted));
+        }
+
+        self.rotate(key, heavy.opposite())
+    }
+
+    /// Rebalance every node on a path from the bottom up, relinking each subtree as it goes
+    fn rebalance_path(&mut self, mut path: Vec<(SlotKey, TreeDirection)>) {
+        while let Some((node_key, _)) = path.pop() {
+            let subtree_root = self.rebalance(node_key);
+            self.link(path.last().copied(), Some(subtree_root));
+        }
+    }
+
+    /// Get the number of nodes on the longest path from the root to a leaf
+    pub fn height(&self) -> usize {
+        rotation::height(&self.storage, self.root)
+    }
+
+    /// Collect references to every value in ascending order
+    pub fn to_vec(&self) -> Vec<&T> {
+        rotation::to_vec(&self.storage, self.root)
+    }
+
+    /// Get the value at a position in ascending order, counting from zero
+    pub fn nth(&self, mut index: usize) -> Option<&T> {
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            let current_node = self.storage.get(current_key);
+            let left_size = self.size(current_node.left);
+
+            current = match index.cmp(&left_size) {
+                Ordering::Equal => return Some(&current_node.data),
+                Ordering::Less => current_node.left,
+                Ordering::Greater => {
+                    index -= left_size + 1;
+                    current_node.right
+                }
+            };
+        }
+
+        None
+    }
+}
+
+impl<T> WbTree<T>
+where
+    T: Eq + Ord + Debug,
+{
+    /// Insert data into the tree
+    ///
+    /// # NOTE
+    ///
+    /// If the data already exists in the tree, then it just returns the data
+    pub fn insert(&mut self, data: T) -> Result<(), T> {
+        // The nodes passed on the way down, and which way we went at each
+        let (path, found) = rotation::descend(&self.storage, self.root, &data);
+        if found.is_some() {
+            // We cannot accept duplicates
+            return Err(data);
+        }
+
+        let new_key = self.storage.insert(WbNode::new(data));
+        self.link(path.last().copied(), Some(new_key));
+
+        // Every node passed grew by one, so each may now be out of balance
+        self.rebalance_path(path);
+
+        Ok(())
+    }
+
+    /// Check if tree contains
+    pub fn contains(&self, data: &T) -> bool {
+        rotation::contains(&self.storage, self.root, data)
+    }
+
+    /// Remove data from the tree, returning it if it existed
+    pub fn remove(&mut self, data: &T) -> Option<T> {
+        // Locate the node holding the data
+        let (mut path, found) = rotation::descend(&self.storage, self.root, data);
+        let node_key = found?;
+
+        let node = self.storage.get(node_key);
+        let removed = match (node.left, node.right) {
+            (Some(_), Some(right)) => {
+                // Swap in the smallest value on the right, and remove its node instead
+                path.push((node_key, TreeDirection::Right));
+
+                let mut successor = right;
+                while let Some(left) = self.storage.get(successor).left {
+                    path.push((successor, TreeDirection::Left));
+                    successor = left;
+                }
+
+                let successor_node = self.storage.remove(successor);
+                self.link(path.last().copied(), successor_node.right);
+
+                core::mem::replace(
+                    &mut self.storage.get_mut(node_key).data,
+                    successor_node.data,
+                )
+            }
+            (child, None) | (None, child) => {
+                self.link(path.last().copied(), child);
+
+                self.storage.remove(node_key).data
+            }
+        };
+
+        // Every node passed shrank by one, so each may now be out of balance
+        self.rebalance_path(path);
+
+        Some(removed)
+    }
+
+    /// Count the values strictly less than data
+    pub fn rank(&self, data: &T) -> usize {
+        let mut rank = 0;
+        let mut current = self.root;
+
+        while let Some(current_key) = current {
+            let current_node = self.storage.get(current_key);
+
+            current = match data.cmp(&current_node.data) {
+                Ordering::Equal => return rank + self.size(current_node.left),
+                Ordering::Less => current_node.left,
+                Ordering::Greater => {
+                    rank += self.size(current_node.left) + 1;
+                    current_node.right
+                }
+            };
+        }
+
+        rank
+    }
+}